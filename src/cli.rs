@@ -0,0 +1,239 @@
+//! Command line subcommands. Only play opens a window; the rest are meant for scripting
+//! level pipelines, so they print plain text and say how things went in the exit code.
+
+use load_world;
+use generate;
+use headless;
+use level;
+use lurd;
+use solver::{self, Outcome};
+use validate;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: sokoban-prototype <command> ...
+  play [<level>] [--vsync] [--undo-walk-steps]
+      Play a level, or the demo room if none is given
+  solve <level> [--max-states <n>]
+      Print the shortest solution in LURD
+  verify <level> <solution file>
+      Check a LURD solution against a level
+  render <level> <out.bmp>
+      Save a picture of a level
+  validate <pack>
+      Check every level in a pack file, or in every file in a directory
+  generate <out file> [--size <width>x<height>] [--blocks <n>] [--seed <n>]
+      Make a new random level
+Exit codes: 0 success, 1 unsolved, unsolvable or invalid, 2 bad arguments or files, 3 solver gave up";
+
+const SUCCESS: i32 = 0;
+// The command ran fine, but the answer was no
+const FAILURE: i32 = 1;
+// Bad arguments, or files that couldn't be read or written
+const ERROR: i32 = 2;
+// The solver looked at too many positions to decide either way
+const GAVE_UP: i32 = 3;
+
+/// Run a command line (without the program name) and return the exit code
+pub fn run(args: Vec<String>) -> i32 {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        // Double-clicking the program should still start the game
+        None => ("play", &[] as &[String]),
+    };
+    let result = match command {
+        "play" => play(args),
+        "solve" => solve(args),
+        "verify" => verify(args),
+        "render" => render(args),
+        "validate" => validate_pack(args),
+        "generate" => generate(args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(SUCCESS)
+        },
+        _ => Err(format!("Unknown command {:?}\n{}", command, USAGE)),
+    };
+    // Errors go to stderr, so they never end up in a file that a result was piped into
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        ERROR
+    })
+}
+
+fn bad_argument(arg: &str) -> String {
+    format!("Unrecognized argument {:?}\n{}", arg, USAGE)
+}
+
+// The value after a flag, which has to be a number
+fn number<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} needs a number, not {:?}", flag, value.map_or("", |value| value.as_str())))
+}
+
+// Exactly this many arguments, none of them flags
+fn positional(args: &[String], count: usize) -> Result<Vec<&str>, String> {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with("--")) {
+        return Err(bad_argument(flag));
+    }
+    if args.len() != count {
+        return Err(format!("Expected {} arguments, got {}\n{}", count, args.len(), USAGE));
+    }
+    Ok(args.iter().map(|arg| arg.as_str()).collect())
+}
+
+fn play(args: &[String]) -> Result<i32, String> {
+    let mut level_path = None;
+    let mut vsync = false;
+    // Undo a click-to-walk one step at a time, instead of all at once
+    let mut undo_walk_steps = false;
+    for arg in args {
+        match arg.as_str() {
+            "--vsync" => vsync = true,
+            "--undo-walk-steps" => undo_walk_steps = true,
+            _ if level_path.is_none() && !arg.starts_with("--") => level_path = Some(arg.clone()),
+            _ => return Err(bad_argument(arg)),
+        }
+    }
+    let world_map = load_world(&level_path)?;
+    ::play(world_map, level_path, vsync, undo_walk_steps);
+    Ok(SUCCESS)
+}
+
+// The solution goes to stdout on its own, so it can be piped straight into a file for verify
+fn solve(args: &[String]) -> Result<i32, String> {
+    let mut level_path = None;
+    let mut max_states = solver::DEFAULT_MAX_STATES;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-states" => max_states = number(arg, args.next())?,
+            _ if level_path.is_none() && !arg.starts_with("--") => level_path = Some(arg.clone()),
+            _ => return Err(bad_argument(arg)),
+        }
+    }
+    let path = level_path.ok_or_else(|| format!("solve needs a level\n{}", USAGE))?;
+    let mut map = level::load_level(&path)?;
+    let search = solver::solve(&mut map, max_states);
+    match search.outcome {
+        Outcome::Solved(moves) => {
+            let solution = lurd::encode(&mut map, &moves);
+            println!("{}", solution);
+            eprintln!("Solved in {} moves ({} positions searched)", moves.len(), search.states);
+            Ok(SUCCESS)
+        },
+        Outcome::Unsolvable => {
+            eprintln!("No solution ({} positions searched)", search.states);
+            Ok(FAILURE)
+        },
+        Outcome::GaveUp => {
+            eprintln!("Gave up after {} positions", search.states);
+            Ok(GAVE_UP)
+        },
+    }
+}
+
+fn verify(args: &[String]) -> Result<i32, String> {
+    let args = positional(args, 2)?;
+    let mut map = level::load_level(args[0])?;
+    let solution = fs::read_to_string(args[1]).map_err(|e| format!("Couldn't read {}: {}", args[1], e))?;
+    let result = lurd::verify(&mut map, &solution)?;
+    if let Some((step, ref error)) = result.illegal {
+        println!("Move {} is illegal: {}", step + 1, error);
+    }
+    println!("{} moves, {} pushes, {}", result.moves, result.pushes,
+        if result.solved { "solved" } else { "not solved" });
+    Ok(if result.solved { SUCCESS } else { FAILURE })
+}
+
+fn render(args: &[String]) -> Result<i32, String> {
+    let args = positional(args, 2)?;
+    let map = level::load_level(args[0])?;
+    headless::render_to_bmp(&map, args[1]).map_err(|e| format!("Failed to render {}: {}", args[1], e))?;
+    Ok(SUCCESS)
+}
+
+// A pack is a file of levels (possibly just one), or a directory of them
+fn validate_pack(args: &[String]) -> Result<i32, String> {
+    let args = positional(args, 1)?;
+    let pack = Path::new(args[0]);
+    let mut paths: Vec<PathBuf> = if pack.is_dir() {
+        let entries = fs::read_dir(pack).map_err(|e| format!("Couldn't read {}: {}", pack.display(), e))?;
+        entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect()
+    } else {
+        vec!(pack.to_path_buf())
+    };
+    paths.sort();
+    let mut checked = 0;
+    let mut failed = 0;
+    for path in paths.iter() {
+        // A file or level that doesn't even load counts as one more problem with the pack
+        let levels = level::load_pack(path).unwrap_or_else(|e| vec!(Err(e)));
+        for (i, loaded) in levels.iter().enumerate() {
+            let problems = match loaded {
+                Ok(map) => validate::validate(map),
+                Err(e) => vec!(e.clone()),
+            };
+            let name = if levels.len() > 1 {
+                format!("{} level {}", path.display(), i + 1)
+            } else {
+                path.display().to_string()
+            };
+            for problem in problems.iter() {
+                println!("{}: {}", name, problem);
+            }
+            checked += 1;
+            if !problems.is_empty() {
+                failed += 1;
+            }
+        }
+    }
+    println!("{} levels checked, {} with problems", checked, failed);
+    Ok(if failed == 0 { SUCCESS } else { FAILURE })
+}
+
+fn generate(args: &[String]) -> Result<i32, String> {
+    let mut out_path = None;
+    let mut options = generate::Options {
+        width: 8,
+        height: 8,
+        blocks: 3,
+        seed: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |t| t.as_secs()),
+        attempts: generate::DEFAULT_ATTEMPTS,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => {
+                let size = args.next().map_or("", |size| size.as_str());
+                let parsed = size.split_once('x').and_then(|(w, h)| w.parse().ok().zip(h.parse().ok()));
+                let (width, height) = parsed.ok_or_else(|| format!("Expected a size like 8x8, not {:?}", size))?;
+                options.width = width;
+                options.height = height;
+            },
+            "--blocks" => options.blocks = number(arg, args.next())?,
+            "--seed" => options.seed = number(arg, args.next())?,
+            _ if out_path.is_none() && !arg.starts_with("--") => out_path = Some(arg.clone()),
+            _ => return Err(bad_argument(arg)),
+        }
+    }
+    let path = out_path.ok_or_else(|| format!("generate needs a file to write\n{}", USAGE))?;
+    // Not finding anything in time is a no, not a mistake in the arguments
+    let generated = match generate::generate(&options) {
+        Ok(generated) => generated,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(FAILURE);
+        },
+    };
+    let text = level::write_level(&generated.map)?;
+    let summary = format!("Seed {}: solved in {} moves, {} pushes ({} positions searched)",
+        options.seed, generated.moves, generated.pushes, generated.difficulty);
+    fs::write(&path, format!("; {}\n{}", summary, text)).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    println!("Wrote {}. {}", path, summary);
+    Ok(SUCCESS)
+}
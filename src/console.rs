@@ -0,0 +1,247 @@
+use {DeltaFrame, Layer, ObjectKind, UndoStack, WorldMap, EnemyBehavior, Tint, MAX_UNDO_DEPTH, TEXT_COLOR};
+use font::{self, DrawText};
+use level;
+use lurd;
+use progress::{self, Progress};
+use solver::{self, Outcome};
+use validate;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+use std::collections::HashMap;
+
+const BACKGROUND_COLOR: Color = Color { r: 10, g: 10, b: 20, a: 220 };
+const TEXT_SCALE: u32 = 2;
+const LINE_HEIGHT: i32 = (font::GLYPH_HEIGHT * TEXT_SCALE) as i32 + 4;
+// How many lines of earlier commands and output stay on screen
+const LOG_LINES: usize = 12;
+
+const HELP: [&str; 10] = [
+    "load <file>         play a level file",
+    "save <file>         write the current position as a level, if it's valid",
+    "save! <file>        write it even if it isn't",
+    "spawn <what> <x> <y>  add an object (block, wall, goal, key, door,",
+    "                    bomb, switch, breakable, enemy, or a level glyph)",
+    "tp <x> <y>          move the player",
+    "undo [n]            undo n moves",
+    "solve               find and play the shortest solution",
+    "stats               describe the level and the moves so far",
+    "progress            list personal bests for every level played",
+];
+
+/// Developer console, opened with the backtick key
+/// Commands that change the map go through DeltaFrames, so they can be undone like moves.
+pub struct Console {
+    pub open: bool,
+    input: String,
+    // Commands and their output, oldest first
+    log: Vec<String>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            open: false,
+            input: String::new(),
+            log: vec!("Type help for a list of commands".to_string()),
+        }
+    }
+
+    // Typing while the console is open; returns a command when Enter is pressed
+    pub fn handle_event(&mut self, event: &Event) -> Option<String> {
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::Backquote), repeat: false, ..} |
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} => self.open = false,
+            Event::KeyDown {keycode: Some(Keycode::Backspace), ..} => {
+                self.input.pop();
+            },
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} |
+            Event::KeyDown {keycode: Some(Keycode::KpEnter), ..} => {
+                let line = self.input.split_off(0);
+                if !line.trim().is_empty() {
+                    self.log.push(format!("> {}", line));
+                    return Some(line);
+                }
+            },
+            // The backtick that opened the console arrives as text too
+            Event::TextInput {text, ..} => self.input.extend(text.chars().filter(|&c| c != '`')),
+            _ => {},
+        }
+        None
+    }
+
+    pub fn execute(&mut self, line: &str, map: &mut WorldMap, undo_stack: &mut UndoStack, level_path: &mut Option<String>, progress: &Progress) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match (words[0], &words[1..]) {
+            ("help", _) => Ok(HELP.join("\n")),
+            ("load", &[path]) => level::load_level(path).map(|new_map| {
+                *map = new_map;
+                *undo_stack = UndoStack::new(MAX_UNDO_DEPTH);
+                *level_path = Some(path.to_string());
+                match progress.get(&progress::level_key(level_path)) {
+                    Some(record) => format!("Loaded {} ({})", path, record.describe()),
+                    None => format!("Loaded {}", path),
+                }
+            }),
+            ("save", &[path]) => {
+                let problems = validate::validate(map);
+                if problems.is_empty() {
+                    level::save_level(map, path).map(|_| format!("Saved {}", path))
+                } else {
+                    Err(format!("{}\nNot saved; use save! to save anyway", problems.join("\n")))
+                }
+            },
+            ("save!", &[path]) => level::save_level(map, path).map(|_| format!("Saved {}", path)),
+            ("spawn", &[what, x, y]) => parse_pos(x, y).and_then(|pos| spawn(map, undo_stack, what, pos)),
+            ("tp", &[x, y]) => parse_pos(x, y).and_then(|pos| teleport(map, undo_stack, pos)),
+            ("undo", args) if args.len() <= 1 => {
+                let count = match args.first() {
+                    Some(n) => n.parse::<usize>().map_err(|_| format!("{:?} isn't a number", n)),
+                    None => Ok(1),
+                };
+                count.map(|count| {
+                    let undone = (0..count).take_while(|_| undo_stack.pop(map)).count();
+                    format!("Undid {} moves", undone)
+                })
+            },
+            ("solve", &[]) => Ok(solve(map, undo_stack)),
+            ("stats", &[]) => Ok(stats(map, undo_stack)),
+            ("progress", &[]) => {
+                let lines: Vec<String> = progress.records()
+                    .map(|(level, record)| format!("{}: {}", level, record.describe()))
+                    .collect();
+                Ok(if lines.is_empty() { "No levels played yet".to_string() } else { lines.join("\n") })
+            },
+            _ => Err(format!("Can't do {:?}; try help", line.trim())),
+        };
+        let text = result.unwrap_or_else(|e| e);
+        self.log.extend(text.lines().map(|line| line.to_string()));
+    }
+
+    pub fn draw(&self, canvas: &mut WindowCanvas) {
+        let (width, _) = canvas.output_size().expect("Failed to get screen size");
+        let start = self.log.len().saturating_sub(LOG_LINES);
+        let lines = &self.log[start..];
+        let height = ((lines.len() + 1) as i32 * LINE_HEIGHT + 8) as u32;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(BACKGROUND_COLOR);
+        canvas.fill_rect(Rect::new(0, 0, width, height)).expect("Failed to draw console");
+        canvas.set_blend_mode(BlendMode::None);
+        for (i, line) in lines.iter().enumerate() {
+            canvas.draw_text(line, (8, 4 + i as i32 * LINE_HEIGHT), TEXT_SCALE, TEXT_COLOR);
+        }
+        let prompt = format!("> {}_", self.input);
+        canvas.draw_text(&prompt, (8, 4 + lines.len() as i32 * LINE_HEIGHT), TEXT_SCALE, TEXT_COLOR);
+    }
+}
+
+fn parse_pos(x: &str, y: &str) -> Result<(i32, i32), String> {
+    match (x.parse::<i32>(), y.parse::<i32>()) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        _ => Err(format!("{} {} isn't a position", x, y)),
+    }
+}
+
+fn spawn(map: &mut WorldMap, undo_stack: &mut UndoStack, what: &str, (x, y): (i32, i32)) -> Result<String, String> {
+    let kind = match what {
+        "block" => ObjectKind::Block(Tint::Plain),
+        "wall" => ObjectKind::Wall,
+        "goal" => ObjectKind::Goal(Tint::Plain),
+        "key" => ObjectKind::Key(Tint::Plain),
+        "door" => ObjectKind::Door(Tint::Plain),
+        "bomb" => ObjectKind::Bomb,
+        "switch" => ObjectKind::Switch,
+        "breakable" => ObjectKind::BreakableWall,
+        "enemy" => ObjectKind::Enemy(EnemyBehavior::Chase),
+        _ => {
+            // Anything else has to be a level glyph standing for exactly one object
+            let kinds = what.chars().next().filter(|_| what.chars().count() == 1).and_then(level::glyph_kinds);
+            match kinds.as_deref() {
+                Some(&[kind]) if kind != ObjectKind::Player => kind,
+                _ => return Err(format!("Don't know how to spawn {:?}", what)),
+            }
+        },
+    };
+    if map.invalid(x, y) {
+        return Err(format!("{:?} is outside the level", (x, y)));
+    }
+    let object = level::make_object(kind, (x, y));
+    let layer = object.get_layer();
+    if map.peek(x, y, &layer).is_some() {
+        return Err(format!("There's already something on the {:?} layer at {:?}", layer, (x, y)));
+    }
+    let id = object.get_id();
+    let mut frame = DeltaFrame::new();
+    map.put(object, &mut frame);
+    undo_stack.push(frame);
+    Ok(format!("Spawned {:?} #{} at {:?}", kind, id, (x, y)))
+}
+
+fn teleport(map: &mut WorldMap, undo_stack: &mut UndoStack, (x, y): (i32, i32)) -> Result<String, String> {
+    if map.invalid(x, y) || map.peek(x, y, &Layer::Solid).is_some() {
+        return Err(format!("{:?} isn't free", (x, y)));
+    }
+    let (px, py) = map.get_player_pos();
+    let mut player = map.take_id(px, py, &Layer::Solid, map.get_player_id()).unwrap();
+    let mut frame = DeltaFrame::new();
+    player.shift_pos((x - px, y - py), &mut frame);
+    map.put_quiet(player);
+    undo_stack.push(frame);
+    Ok(format!("Teleported to {:?} (this won't show up in exported solutions)", (x, y)))
+}
+
+// Play the solution one undoable move at a time, but so a single undo takes it all back
+fn solve(map: &mut WorldMap, undo_stack: &mut UndoStack) -> String {
+    let search = solver::solve(map, solver::DEFAULT_MAX_STATES);
+    match search.outcome {
+        Outcome::Solved(moves) => {
+            let mut solution = String::new();
+            for (i, &dir) in moves.iter().enumerate() {
+                let mut frame = DeltaFrame::new();
+                map.take_turn(dir, &mut frame);
+                let pushed = frame.player_motion(map.get_player_id()).is_some_and(|(_, pushed)| pushed);
+                solution.push(lurd::move_char(dir, pushed));
+                if i == 0 {
+                    undo_stack.push(frame);
+                } else {
+                    undo_stack.push_joined(frame);
+                }
+            }
+            format!("Solved in {} moves ({} positions searched): {}", moves.len(), search.states, solution)
+        },
+        Outcome::Unsolvable => format!("No solution from here ({} positions searched)", search.states),
+        Outcome::GaveUp => format!("Gave up after {} positions", search.states),
+    }
+}
+
+fn stats(map: &WorldMap, undo_stack: &UndoStack) -> String {
+    let solution = lurd::export(undo_stack, map.get_player_id());
+    let pushes = solution.chars().filter(|c| c.is_ascii_uppercase()).count();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for x in 0..map.width {
+        for y in 0..map.height {
+            for layer in [Layer::Floor, Layer::Player, Layer::Solid].iter() {
+                for object in map.map[x as usize][y as usize].layers[Layer::index(layer)].iter() {
+                    *counts.entry(format!("{:?}", object.kind())).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort();
+    let counts: Vec<String> = counts.into_iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+    let strength = match map.push_strength {
+        Some(n) => n.to_string(),
+        None => "unlimited".to_string(),
+    };
+    format!(
+        "{}x{} level, push strength {}\n{} moves, {} pushes{}\nObjects: {}",
+        map.width, map.height, strength,
+        solution.len(), pushes, if map.solved() { ", solved" } else { "" },
+        counts.join(", "),
+    )
+}
@@ -0,0 +1,236 @@
+//! Random level generation. Rooms are carved out at random, then the blocks start on their
+//! goals and get pulled away from them by a player walking backwards, which can only ever
+//! make solvable positions. The solver then confirms each candidate and measures how hard it is.
+
+use {WorldMap, DIRECTIONS};
+use level;
+use lurd;
+use solver::{self, Outcome};
+use validate;
+
+use std::collections::{HashSet, VecDeque};
+
+// Roughly one interior cell in this many starts out as a wall
+const WALL_CHANCE: usize = 6;
+// Steps taken by the player walking backwards, per floor cell
+const PULL_STEPS_PER_CELL: usize = 8;
+// The solver gives up on a candidate after this many positions, so generating stays quick
+const MAX_STATES: usize = 50_000;
+
+/// What to generate
+pub struct Options {
+    // Including the outer walls
+    pub width: i32,
+    pub height: i32,
+    pub blocks: usize,
+    pub seed: u64,
+    // Candidates to try; the hardest solvable one wins
+    pub attempts: usize,
+}
+
+/// Enough tries to usually find something worth playing
+pub const DEFAULT_ATTEMPTS: usize = 20;
+
+/// A generated level, along with how its solution went
+pub struct Generated {
+    pub map: WorldMap,
+    pub moves: usize,
+    pub pushes: usize,
+    // Positions the solver had to look at, as a rough measure of difficulty
+    pub difficulty: usize,
+}
+
+// Small xorshift generator, so levels come out the same for the same seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Xorshift gets stuck at zero
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.next() as usize % n
+    }
+}
+
+/// The hardest solvable level found in the given number of attempts
+pub fn generate(options: &Options) -> Result<Generated, String> {
+    if options.width < 4 || options.height < 4 {
+        return Err("Levels have to be at least 4x4".to_string());
+    }
+    if options.blocks == 0 {
+        return Err("Levels need at least one block".to_string());
+    }
+    let mut rng = Rng::new(options.seed);
+    let mut best: Option<Generated> = None;
+    for _ in 0..options.attempts {
+        let text = match candidate(options, &mut rng) {
+            Some(text) => text,
+            None => continue,
+        };
+        let mut map = level::parse_level(&text)?;
+        if !validate::validate(&map).is_empty() {
+            continue;
+        }
+        let search = solver::solve(&mut map, MAX_STATES);
+        let states = search.states;
+        let moves = match search.outcome {
+            Outcome::Solved(moves) => moves,
+            _ => continue,
+        };
+        if moves.is_empty() || best.as_ref().is_some_and(|best| best.difficulty >= states) {
+            continue;
+        }
+        let pushes = lurd::encode(&mut map, &moves).chars().filter(|c| c.is_ascii_uppercase()).count();
+        best = Some(Generated {
+            map,
+            moves: moves.len(),
+            pushes,
+            difficulty: states,
+        });
+    }
+    best.ok_or_else(|| format!("Couldn't make a {}x{} level with {} blocks in {} attempts",
+        options.width, options.height, options.blocks, options.attempts))
+}
+
+// One random level, as level text, or None if the room came out too cramped
+fn candidate(options: &Options, rng: &mut Rng) -> Option<String> {
+    let (width, height) = (options.width as usize, options.height as usize);
+    let mut wall = vec![vec![true; height]; width];
+    for column in wall[1..width - 1].iter_mut() {
+        for cell in column[1..height - 1].iter_mut() {
+            *cell = rng.below(WALL_CHANCE) == 0;
+        }
+    }
+    let floor = largest_room(&mut wall);
+    // Goals, blocks and the player all need their own cell
+    if floor.len() < options.blocks * 2 + 1 {
+        return None;
+    }
+
+    let mut free = floor.clone();
+    let mut goals = vec!();
+    for _ in 0..options.blocks {
+        goals.push(free.swap_remove(rng.below(free.len())));
+    }
+    let mut blocks: HashSet<(usize, usize)> = goals.iter().cloned().collect();
+    let mut player = free[rng.below(free.len())];
+
+    // Walk backwards, sometimes dragging along a block from the cell behind
+    for _ in 0..floor.len() * PULL_STEPS_PER_CELL {
+        let (dx, dy) = DIRECTIONS[rng.below(DIRECTIONS.len())];
+        let next = ((player.0 as i32 + dx) as usize, (player.1 as i32 + dy) as usize);
+        if wall[next.0][next.1] || blocks.contains(&next) {
+            continue;
+        }
+        let behind = ((player.0 as i32 - dx) as usize, (player.1 as i32 - dy) as usize);
+        if blocks.contains(&behind) && rng.below(2) == 0 {
+            blocks.remove(&behind);
+            blocks.insert(player);
+        }
+        player = next;
+    }
+    // Starting out solved isn't much of a puzzle
+    if goals.iter().all(|goal| blocks.contains(goal)) {
+        return None;
+    }
+
+    let mut text = String::new();
+    for y in 0..height {
+        for (x, column) in wall.iter().enumerate() {
+            let pos = (x, y);
+            let glyph = match (column[y], blocks.contains(&pos), goals.contains(&pos), pos == player) {
+                (true, _, _, _) => '#',
+                (_, true, true, _) => '*',
+                (_, true, false, _) => '$',
+                (_, _, true, true) => '+',
+                (_, _, false, true) => '@',
+                (_, _, true, _) => '.',
+                _ => ' ',
+            };
+            text.push(glyph);
+        }
+        text.push('\n');
+    }
+    Some(text)
+}
+
+// Wall off everything but the biggest connected patch of floor, and return that patch
+fn largest_room(wall: &mut [Vec<bool>]) -> Vec<(usize, usize)> {
+    let mut seen: HashSet<(usize, usize)> = HashSet::new();
+    let mut best = vec!();
+    for (x, column) in wall.iter().enumerate() {
+        for (y, &is_wall) in column.iter().enumerate() {
+            if is_wall || seen.contains(&(x, y)) {
+                continue;
+            }
+            let mut room = vec!((x, y));
+            seen.insert((x, y));
+            let mut queue = VecDeque::new();
+            queue.push_back((x, y));
+            while let Some((cx, cy)) = queue.pop_front() {
+                for &(dx, dy) in DIRECTIONS.iter() {
+                    // The outer wall keeps this in bounds
+                    let next = ((cx as i32 + dx) as usize, (cy as i32 + dy) as usize);
+                    if !wall[next.0][next.1] && seen.insert(next) {
+                        room.push(next);
+                        queue.push_back(next);
+                    }
+                }
+            }
+            if room.len() > best.len() {
+                best = room;
+            }
+        }
+    }
+    for column in wall.iter_mut() {
+        for cell in column.iter_mut() {
+            *cell = true;
+        }
+    }
+    for &(x, y) in best.iter() {
+        wall[x][y] = false;
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(seed: u64) -> Options {
+        Options {
+            width: 7,
+            height: 7,
+            blocks: 2,
+            seed,
+            attempts: 5,
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_level() {
+        let first = level::write_level(&generate(&options(42)).unwrap().map).unwrap();
+        let second = level::write_level(&generate(&options(42)).unwrap().map).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn generated_levels_are_valid_and_solvable() {
+        for seed in 0..3 {
+            let text = level::write_level(&generate(&options(seed)).unwrap().map).unwrap();
+            let mut map = level::parse_level(&text).unwrap();
+            assert!(validate::validate(&map).is_empty(), "seed {} made an invalid level:\n{}", seed, text);
+            assert!(matches!(solver::solve(&mut map, MAX_STATES).outcome, Outcome::Solved(ref moves) if !moves.is_empty()),
+                "seed {} made an unsolvable level:\n{}", seed, text);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use {DrawContext, WorldMap, BACKGROUND_COLOR, MESH};
+use sprite::{self, TileAtlas};
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::surface::Surface;
+
+use std::path::Path;
+
+/// Draw the whole map into an offscreen software canvas and save it as a BMP
+/// This never starts SDL's video subsystem, so it works on machines with no display
+pub fn render_to_bmp<P: AsRef<Path>>(map: &WorldMap, path: P) -> Result<(), String> {
+    let width = (map.width * MESH) as u32;
+    let height = (map.height * MESH) as u32;
+    let surface = Surface::new(width, height, PixelFormatEnum::ARGB8888)?;
+    let mut canvas = surface.into_canvas()?;
+
+    // Thumbnails should look like the game, so use the atlas if there is one
+    let texture_creator = canvas.texture_creator();
+    let atlas = TileAtlas::load(&texture_creator, sprite::ATLAS_PATH).ok();
+
+    canvas.set_draw_color(BACKGROUND_COLOR);
+    canvas.clear();
+    map.draw(&mut canvas, &DrawContext::new(atlas.as_ref()));
+    canvas.into_surface().save_bmp(path)
+}
@@ -0,0 +1,488 @@
+use {Block, Bomb, Conveyor, Door, Enemy, EnemyBehavior, GameObject, Goal, Key, Layer, ObjectKind, Player, Switch, Tint, WorldMap, DEFAULT_STRENGTH, MAX_OBJECT_SIZE};
+
+use std::fs;
+use std::path::Path;
+
+/// Levels use the standard Sokoban text format (as in XSB files), with a few extensions:
+///   # wall    @ player    + player on goal
+///   $ block   * block on goal    . goal    space, - or _ floor
+///   R G B Y   red, green, blue and yellow blocks
+///   r g b y   goals of those colors
+///   k         key             K         door
+///   1 2 3 4   red, green, blue and yellow keys
+///   5 6 7 8   red, green, blue and yellow doors
+///   < > ^ v   conveyor belts running that way
+///   %         breakable wall  !         bomb      =   switch
+///   E         enemy that chases the player
+///   H V       enemies patrolling side to side (starting right) or up and down (starting down)
+/// Lines starting with ';' are comments, and so are metadata lines like "Title: ..." or
+/// "Author: ...". A "Comment:" line with nothing after it starts a comment that runs
+/// until a "Comment-End:" line.
+///
+/// A file can hold a whole pack of levels, as XSB files usually do, separated by blank lines.
+/// Metadata can go before or after a level's grid.
+///
+/// A line reading "Strength: N" limits the player to pushing N objects at once, and
+/// "Strength: unlimited" lifts the limit. Levels default to the classic strength of 1.
+///
+/// A cell can only hold one glyph, so floor objects that share a cell with something
+/// else (like a red block on a red goal) go in an optional second grid, which starts
+/// after a line reading FLOOR_SECTION and lines up with the first one.
+pub const FLOOR_SECTION: &str = "[floor]";
+
+/// Blocks bigger than one cell are listed after a line reading BIG_BLOCK_SECTION,
+/// one per line as "column line width height", plus an optional color letter.
+/// Column and line are those of the block's top left cell in the main grid.
+pub const BIG_BLOCK_SECTION: &str = "[big blocks]";
+
+const STRENGTH_KEY: &str = "Strength:";
+const COMMENT_KEY: &str = "Comment";
+const COMMENT_END_KEY: &str = "Comment-End";
+
+const TINT_LETTERS: [(char, Tint); 4] = [
+    ('r', Tint::Red),
+    ('g', Tint::Green),
+    ('b', Tint::Blue),
+    ('y', Tint::Yellow),
+];
+
+// Keys and doors in the same order as TINT_LETTERS
+const KEY_DIGITS: [char; 4] = ['1', '2', '3', '4'];
+const DOOR_DIGITS: [char; 4] = ['5', '6', '7', '8'];
+
+// Everything a glyph stands for
+pub fn glyph_kinds(glyph: char) -> Option<Vec<ObjectKind>> {
+    let kinds = match glyph {
+        '#' => vec!(ObjectKind::Wall),
+        '@' => vec!(ObjectKind::Player),
+        '+' => vec!(ObjectKind::Player, ObjectKind::Goal(Tint::Plain)),
+        '$' => vec!(ObjectKind::Block(Tint::Plain)),
+        '*' => vec!(ObjectKind::Block(Tint::Plain), ObjectKind::Goal(Tint::Plain)),
+        '.' => vec!(ObjectKind::Goal(Tint::Plain)),
+        ' ' | '-' | '_' => vec!(),
+        'k' => vec!(ObjectKind::Key(Tint::Plain)),
+        'K' => vec!(ObjectKind::Door(Tint::Plain)),
+        '<' => vec!(ObjectKind::Conveyor((-1, 0))),
+        '>' => vec!(ObjectKind::Conveyor((1, 0))),
+        '^' => vec!(ObjectKind::Conveyor((0, -1))),
+        'v' => vec!(ObjectKind::Conveyor((0, 1))),
+        '%' => vec!(ObjectKind::BreakableWall),
+        '!' => vec!(ObjectKind::Bomb),
+        '=' => vec!(ObjectKind::Switch),
+        'E' => vec!(ObjectKind::Enemy(EnemyBehavior::Chase)),
+        'H' => vec!(ObjectKind::Enemy(EnemyBehavior::Patrol((1, 0)))),
+        'V' => vec!(ObjectKind::Enemy(EnemyBehavior::Patrol((0, 1)))),
+        '1'..='4' => vec!(ObjectKind::Key(TINT_LETTERS[KEY_DIGITS.iter().position(|&d| d == glyph)?].1)),
+        '5'..='8' => vec!(ObjectKind::Door(TINT_LETTERS[DOOR_DIGITS.iter().position(|&d| d == glyph)?].1)),
+        _ => {
+            let &(_, tint) = TINT_LETTERS.iter().find(|&&(letter, _)| letter == glyph.to_ascii_lowercase())?;
+            if glyph.is_ascii_uppercase() {
+                vec!(ObjectKind::Block(tint))
+            } else {
+                vec!(ObjectKind::Goal(tint))
+            }
+        },
+    };
+    Some(kinds)
+}
+
+// The glyph standing for exactly these kinds, if there is one
+fn glyph_for(kinds: &[ObjectKind]) -> Option<char> {
+    (' '..='~').find(|&glyph| glyph_kinds(glyph).is_some_and(|k| k == kinds))
+}
+
+// Patrollers can be walking either way, but the level format only has glyphs for them starting right or down
+fn canonical(kind: ObjectKind) -> ObjectKind {
+    match kind {
+        ObjectKind::Enemy(EnemyBehavior::Patrol((dx, dy))) => ObjectKind::Enemy(EnemyBehavior::Patrol((dx.abs(), dy.abs()))),
+        _ => kind,
+    }
+}
+
+pub fn make_object(kind: ObjectKind, (x, y): (i32, i32)) -> Box<dyn GameObject> {
+    match kind {
+        ObjectKind::Player => Box::new(Player::new(x, y)),
+        ObjectKind::Block(tint) => Box::new(Block::new_tinted_block(x, y, tint)),
+        ObjectKind::Wall => Box::new(Block::new_wall(x, y)),
+        ObjectKind::Goal(tint) => Box::new(Goal::new(x, y, tint)),
+        ObjectKind::Key(tint) => Box::new(Key::new(x, y, tint)),
+        ObjectKind::Door(tint) => Box::new(Door::new(x, y, tint)),
+        ObjectKind::Conveyor(dir) => Box::new(Conveyor::new(x, y, dir)),
+        ObjectKind::BreakableWall => Box::new(Block::new_breakable_wall(x, y)),
+        ObjectKind::Bomb => Box::new(Bomb::new(x, y)),
+        ObjectKind::Switch => Box::new(Switch::new(x, y)),
+        ObjectKind::Enemy(behavior) => Box::new(Enemy::new(x, y, behavior)),
+    }
+}
+
+// A "Key: value" line; grid rows and big block lines never have a ':' in them
+fn metadata(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.trim().split_once(':')?;
+    if key.is_empty() {
+        return None;
+    }
+    Some((key, value.trim()))
+}
+
+fn starts_comment(line: &str) -> bool {
+    metadata(line).is_some_and(|(key, value)| key.eq_ignore_ascii_case(COMMENT_KEY) && value.is_empty())
+}
+
+fn ends_comment(line: &str) -> bool {
+    metadata(line).is_some_and(|(key, _)| key.eq_ignore_ascii_case(COMMENT_END_KEY))
+}
+
+/// The text of each level in a pack, in order
+/// A blank line ends a level once it has a grid, except in the floor grid, whose rows can be blank
+/// (a row with a '#' in it can't be part of a floor grid, so that still starts a new level).
+pub fn split_pack(text: &str) -> Vec<String> {
+    let mut levels = vec!();
+    let mut current: Vec<&str> = vec!();
+    let mut has_grid = false;
+    let mut section: Option<&str> = None;
+    let mut in_comment = false;
+    // Blank lines since the last one that wasn't
+    let mut blanks = 0;
+    for line in text.lines().map(|line| line.trim_end_matches('\r')) {
+        if line.trim().is_empty() && !in_comment {
+            blanks += 1;
+            continue;
+        }
+        let floor_row = section == Some(FLOOR_SECTION) && !line.contains('#') && !line.starts_with(';') && metadata(line).is_none();
+        if blanks > 0 && has_grid && !floor_row && !in_comment {
+            levels.push(current.join("\n") + "\n");
+            current.clear();
+            has_grid = false;
+            section = None;
+        } else {
+            current.extend(std::iter::repeat_n("", blanks));
+        }
+        blanks = 0;
+        current.push(line);
+        if in_comment {
+            in_comment = !ends_comment(line);
+        } else if starts_comment(line) {
+            in_comment = true;
+        } else if !line.starts_with(';') && metadata(line).is_none() {
+            match line.trim() {
+                FLOOR_SECTION => section = Some(FLOOR_SECTION),
+                BIG_BLOCK_SECTION => section = Some(BIG_BLOCK_SECTION),
+                _ => has_grid |= section.is_none(),
+            }
+        }
+    }
+    if has_grid {
+        levels.push(current.join("\n") + "\n");
+    }
+    levels
+}
+
+fn parse_strength(value: &str) -> Result<Option<usize>, String> {
+    if value.eq_ignore_ascii_case("unlimited") {
+        return Ok(None);
+    }
+    match value.parse::<usize>() {
+        Ok(strength) if strength > 0 => Ok(Some(strength)),
+        _ => Err(format!("Strength must be a positive number or \"unlimited\", not {:?}", value)),
+    }
+}
+
+fn parse_big_block(row: &str, map: &WorldMap) -> Result<Block, String> {
+    let bad = || format!("Big blocks are written \"column line width height [color]\", not {:?}", row);
+    let fields: Vec<&str> = row.split_whitespace().collect();
+    if fields.len() < 4 || fields.len() > 5 {
+        return Err(bad());
+    }
+    let mut numbers = vec!();
+    for field in &fields[..4] {
+        numbers.push(field.parse::<i32>().map_err(|_| bad())?);
+    }
+    let (x, y, width, height) = (numbers[0] - 1, numbers[1] - 1, numbers[2], numbers[3]);
+    let tint = match fields.get(4) {
+        Some(letter) => TINT_LETTERS.iter()
+            .find(|&&(c, _)| letter.len() == 1 && letter.starts_with(c))
+            .map(|&(_, tint)| tint)
+            .ok_or_else(bad)?,
+        None => Tint::Plain,
+    };
+    if width < 1 || height < 1 || width > MAX_OBJECT_SIZE || height > MAX_OBJECT_SIZE {
+        return Err(format!("Big blocks can be at most {0}x{0} ({1:?})", MAX_OBJECT_SIZE, row));
+    }
+    let block = Block::new_big_block(x, y, (width, height), tint);
+    for (cx, cy) in block.cells() {
+        if map.invalid(cx, cy) {
+            return Err(format!("Big block at line {}, column {} doesn't fit in the level", y + 1, x + 1));
+        }
+        if map.peek(cx, cy, &Layer::Solid).is_some() {
+            return Err(format!("Big block at line {}, column {} overlaps something at line {}, column {}", y + 1, x + 1, cy + 1, cx + 1));
+        }
+    }
+    Ok(block)
+}
+
+/// Write the map in the same format parse_level reads
+/// Things the format can't hold (the inventory, facings, which way a patroller is
+/// currently walking) are lost.
+pub fn write_level(map: &WorldMap) -> Result<String, String> {
+    let mut main_rows = vec!();
+    let mut floor_rows = vec!();
+    let mut big_blocks = vec!();
+    for y in 0..map.height {
+        let mut main_row = String::new();
+        let mut floor_row = String::new();
+        for x in 0..map.width {
+            // Look in the cell itself, so big blocks only show up in their top left corner
+            let cell = &map.map[x as usize][y as usize];
+            let mut solid = cell.peek(&Layer::Solid).map(|object| canonical(object.kind()));
+            let floor = cell.peek(&Layer::Floor).map(|object| object.kind());
+            if let Some(object) = cell.peek(&Layer::Solid) {
+                if object.size() != (1, 1) {
+                    big_blocks.push((object.get_pos(), object.size(), object.kind()));
+                    solid = None;
+                }
+            }
+            let kinds: Vec<ObjectKind> = solid.into_iter().chain(floor).collect();
+            let unknown = || format!("Can't write {:?} at line {}, column {}", kinds, y + 1, x + 1);
+            match glyph_for(&kinds) {
+                Some(glyph) => {
+                    main_row.push(glyph);
+                    floor_row.push(' ');
+                },
+                // Whatever doesn't fit in one glyph goes in the floor grid
+                None => {
+                    main_row.push(glyph_for(&kinds[..kinds.len() - 1]).ok_or_else(unknown)?);
+                    floor_row.push(glyph_for(&kinds[kinds.len() - 1..]).ok_or_else(unknown)?);
+                },
+            }
+        }
+        // Trailing spaces are easy to lose (and blank edge rows are skipped when loading),
+        // so the end of each row is filled out with '-' to keep the level its full size
+        let trimmed = main_row.trim_end();
+        main_rows.push(format!("{}{}", trimmed, "-".repeat(map.width as usize - trimmed.len())));
+        floor_rows.push(floor_row.trim_end().to_string());
+    }
+
+    let mut text = String::new();
+    if map.push_strength != DEFAULT_STRENGTH {
+        match map.push_strength {
+            Some(strength) => text += &format!("{} {}\n", STRENGTH_KEY, strength),
+            None => text += &format!("{} unlimited\n", STRENGTH_KEY),
+        }
+    }
+    for row in main_rows {
+        text += &row;
+        text += "\n";
+    }
+    if floor_rows.iter().any(|row| !row.is_empty()) {
+        text += FLOOR_SECTION;
+        text += "\n";
+        let last = floor_rows.iter().rposition(|row| !row.is_empty()).unwrap();
+        for row in &floor_rows[..=last] {
+            // A blank line would end the level if it's saved into a pack
+            text += if row.is_empty() { "-" } else { row };
+            text += "\n";
+        }
+    }
+    if !big_blocks.is_empty() {
+        text += BIG_BLOCK_SECTION;
+        text += "\n";
+        for ((x, y), (width, height), kind) in big_blocks {
+            text += &format!("{} {} {} {}", x + 1, y + 1, width, height);
+            if let ObjectKind::Block(tint) = kind {
+                if let Some(&(letter, _)) = TINT_LETTERS.iter().find(|&&(_, t)| t == tint) {
+                    text += &format!(" {}", letter);
+                }
+            }
+            text += "\n";
+        }
+    }
+    Ok(text)
+}
+
+pub fn save_level<P: AsRef<Path>>(map: &WorldMap, path: P) -> Result<(), String> {
+    let path = path.as_ref();
+    let text = write_level(map)?;
+    fs::write(path, text).map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+}
+
+/// Load a level file; a file holding a whole pack loads as its first level
+pub fn load_level<P: AsRef<Path>>(path: P) -> Result<WorldMap, String> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    match split_pack(&text).first() {
+        Some(level) => parse_level(level),
+        None => parse_level(&text),
+    }
+}
+
+/// Load every level in a pack file, each of which may or may not parse
+pub fn load_pack<P: AsRef<Path>>(path: P) -> Result<Vec<Result<WorldMap, String>>, String> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    let levels = split_pack(&text);
+    if levels.is_empty() {
+        return Err(format!("No levels in {}", path.display()));
+    }
+    Ok(levels.iter().map(|level| parse_level(level)).collect())
+}
+
+pub fn parse_level(text: &str) -> Result<WorldMap, String> {
+    let mut push_strength = DEFAULT_STRENGTH;
+    let mut lines: Vec<&str> = vec!();
+    let mut in_comment = false;
+    for line in text.lines().map(|line| line.trim_end_matches('\r')) {
+        if in_comment {
+            in_comment = !ends_comment(line);
+            continue;
+        }
+        if line.starts_with(';') {
+            continue;
+        }
+        if let Some(value) = line.trim().strip_prefix(STRENGTH_KEY) {
+            push_strength = parse_strength(value.trim())?;
+        } else if metadata(line).is_some() {
+            in_comment = starts_comment(line);
+        } else {
+            lines.push(line);
+        }
+    }
+    // The main grid, then any sections in whatever order they come
+    let headers: Vec<usize> = lines.iter()
+        .enumerate()
+        .filter(|(_, line)| [FLOOR_SECTION, BIG_BLOCK_SECTION].contains(&line.trim()))
+        .map(|(i, _)| i)
+        .collect();
+    let main_rows = &lines[..headers.first().cloned().unwrap_or(lines.len())];
+    let section = |name: &str| -> &[&str] {
+        match headers.iter().position(|&i| lines[i].trim() == name) {
+            Some(n) => &lines[headers[n] + 1..headers.get(n + 1).cloned().unwrap_or(lines.len())],
+            None => &[],
+        }
+    };
+    let floor_rows = section(FLOOR_SECTION);
+    let big_block_rows = section(BIG_BLOCK_SECTION);
+    // Blank lines around the level don't count towards its size
+    let first = main_rows.iter().position(|row| !row.trim().is_empty()).ok_or("Level is empty")?;
+    let last = main_rows.iter().rposition(|row| !row.trim().is_empty()).unwrap();
+    let main_rows = &main_rows[first..=last];
+    let width = main_rows.iter().map(|row| row.chars().count()).max().unwrap() as i32;
+    let height = main_rows.len() as i32;
+
+    let mut player_pos = None;
+    let mut objects: Vec<(ObjectKind, (i32, i32))> = vec!();
+    for (y, row) in main_rows.iter().enumerate() {
+        for (x, glyph) in row.chars().enumerate() {
+            let kinds = glyph_kinds(glyph)
+                .ok_or_else(|| format!("Unknown glyph {:?} at line {}, column {}", glyph, y + 1, x + 1))?;
+            for kind in kinds {
+                if kind == ObjectKind::Player {
+                    if player_pos.is_some() {
+                        return Err(format!("Second player at line {}, column {}", y + 1, x + 1));
+                    }
+                    player_pos = Some((x as i32, y as i32));
+                } else {
+                    objects.push((kind, (x as i32, y as i32)));
+                }
+            }
+        }
+    }
+    let (px, py) = player_pos.ok_or("Level has no player")?;
+
+    let mut player = Box::new(Player::new(px, py));
+    let mut map = WorldMap::new(width, height, &mut (*player) as *mut Player);
+    map.push_strength = push_strength;
+    map.put_quiet(player);
+    for (kind, pos) in objects {
+        map.put_quiet(make_object(kind, pos));
+    }
+    for row in big_block_rows.iter().filter(|row| !row.trim().is_empty()) {
+        map.put_quiet(Box::new(parse_big_block(row, &map)?));
+    }
+
+    for (y, row) in floor_rows.iter().enumerate() {
+        for (x, glyph) in row.chars().enumerate() {
+            let kinds = glyph_kinds(glyph)
+                .ok_or_else(|| format!("Unknown glyph {:?} in the floor grid at line {}, column {}", glyph, y + 1, x + 1))?;
+            for kind in kinds {
+                let (x, y) = (x as i32, y as i32);
+                let object = make_object(kind, (x, y));
+                if Layer::index(&object.get_layer()) != Layer::index(&Layer::Floor) {
+                    return Err(format!("Only floor objects can go in the floor grid (line {}, column {})", y + 1, x + 1));
+                }
+                if map.invalid(x, y) {
+                    return Err(format!("Floor grid is bigger than the level (line {}, column {})", y + 1, x + 1));
+                }
+                if map.peek(x, y, &Layer::Floor).is_some() {
+                    return Err(format!("Two floor objects at line {}, column {}", y + 1, x + 1));
+                }
+                map.put_quiet(object);
+            }
+        }
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Saving and loading again gives back exactly the same text
+    fn assert_round_trip(text: &str) {
+        let written = write_level(&parse_level(text).unwrap()).unwrap();
+        assert_eq!(write_level(&parse_level(&written).unwrap()).unwrap(), written);
+    }
+
+    #[test]
+    fn round_trip_keeps_glyphs() {
+        assert_round_trip("#######\n#@ $ .#\n# * R #\n# r1 5#\n#<>^v %#\n#!=EHV#\n#######\n");
+    }
+
+    #[test]
+    fn round_trip_keeps_size_and_positions() {
+        let map = ::demo_map();
+        let loaded = parse_level(&write_level(&map).unwrap()).unwrap();
+        assert_eq!((loaded.width, loaded.height), (map.width, map.height));
+        assert_eq!(loaded.get_player_pos(), map.get_player_pos());
+        assert_eq!(loaded.peek(5, 5, &Layer::Solid).map(|object| object.kind()), Some(ObjectKind::Wall));
+    }
+
+    #[test]
+    fn round_trip_keeps_floor_grid_big_blocks_and_strength() {
+        let text = "Strength: 2\n######\n#@ R #\n#    #\n#    #\n######\n[floor]\n\n   r\n[big blocks]\n2 3 2 2 g\n";
+        let map = parse_level(text).unwrap();
+        assert_eq!(map.push_strength, Some(2));
+        assert_eq!(map.peek(2, 3, &Layer::Solid).map(|object| object.kind()), Some(ObjectKind::Block(Tint::Green)));
+        assert_round_trip(text);
+    }
+
+    #[test]
+    fn files_and_the_demo_room_share_a_push_strength() {
+        let map = parse_level("#####\n#@$.#\n#####\n").unwrap();
+        assert_eq!(map.push_strength, ::demo_map().push_strength);
+    }
+
+    #[test]
+    fn packs_split_into_levels_and_skip_metadata() {
+        let pack = "; Two tiny levels\nTitle: First\n\n#####\n#@$.#\n#####\nAuthor: Someone\n\n\
+            Comment:\nA comment\n\nover several lines\nComment-End:\n######\n#@ $.#\n######\nTitle: Second\n";
+        let levels = split_pack(pack);
+        assert_eq!(levels.len(), 2);
+        assert_eq!(parse_level(&levels[0]).unwrap().width, 5);
+        assert_eq!(parse_level(&levels[1]).unwrap().width, 6);
+
+        // Blank rows in a floor grid belong to it, but a row with walls starts the next level
+        let extended = "######\n#@ R #\n######\n[floor]\n\n   r\n\n#####\n#@$.#\n#####\n";
+        let levels = split_pack(extended);
+        assert_eq!(levels.len(), 2);
+        assert_eq!(parse_level(&levels[0]).unwrap().peek(3, 1, &Layer::Floor).map(|object| object.kind()),
+            Some(ObjectKind::Goal(Tint::Red)));
+        assert_eq!(split_pack(&write_level(&parse_level(&levels[0]).unwrap()).unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn parse_rejects_bad_levels() {
+        assert!(parse_level("#####\n# $.#\n#####\n").is_err());
+        assert!(parse_level("#####\n#@@ #\n#####\n").is_err());
+        assert!(parse_level("#####\n#@ ?#\n#####\n").is_err());
+    }
+}
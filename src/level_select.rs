@@ -0,0 +1,108 @@
+use TEXT_COLOR;
+use font::{self, DrawText};
+use level;
+use progress::{self, Progress};
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+use std::fs;
+use std::path::Path;
+
+const BACKGROUND_COLOR: Color = Color { r: 10, g: 20, b: 10, a: 230 };
+const TEXT_SCALE: u32 = 2;
+const LINE_HEIGHT: i32 = (font::GLYPH_HEIGHT * TEXT_SCALE) as i32 + 4;
+// Levels shown at once; the list scrolls to keep the selected one in view
+const VISIBLE_LINES: usize = 16;
+
+/// List of levels to pick from, with the player's personal best on each, opened with L
+pub struct LevelSelect {
+    pub open: bool,
+    // Canonical paths, as used for keys in the progress file
+    levels: Vec<String>,
+    selected: usize,
+}
+
+impl LevelSelect {
+    pub fn new() -> LevelSelect {
+        LevelSelect {
+            open: false,
+            levels: vec!(),
+            selected: 0,
+        }
+    }
+
+    /// Open the list, with every level next to the current one and every level played before
+    pub fn show(&mut self, level_path: &Option<String>, progress: &Progress) {
+        let mut levels: Vec<String> = progress.records()
+            .map(|(level, _)| level.clone())
+            .filter(|level| Path::new(level).is_file())
+            .collect();
+        if let Some(path) = level_path {
+            let dir = match Path::new(path).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            if let Ok(entries) = fs::read_dir(dir) {
+                // Anything else in the folder (like a readme) is left out
+                let pack = entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.is_file() && level::load_level(path).is_ok())
+                    .map(|path| progress::level_key(&Some(path.display().to_string())));
+                levels.extend(pack);
+            }
+        }
+        levels.sort();
+        levels.dedup();
+        let current = progress::level_key(level_path);
+        self.selected = levels.iter().position(|level| *level == current).unwrap_or(0);
+        self.levels = levels;
+        self.open = true;
+    }
+
+    // Keys while the list is open; returns the level to load when one is picked
+    pub fn handle_event(&mut self, event: &Event) -> Option<String> {
+        match event {
+            Event::KeyDown {keycode: Some(Keycode::L), repeat: false, ..} |
+            Event::KeyDown {keycode: Some(Keycode::Escape), ..} => self.open = false,
+            Event::KeyDown {keycode: Some(Keycode::Up), ..} => self.selected = self.selected.saturating_sub(1),
+            Event::KeyDown {keycode: Some(Keycode::Down), ..} => {
+                self.selected = (self.selected + 1).min(self.levels.len().saturating_sub(1));
+            },
+            Event::KeyDown {keycode: Some(Keycode::Return), ..} |
+            Event::KeyDown {keycode: Some(Keycode::KpEnter), ..} => {
+                if let Some(level) = self.levels.get(self.selected) {
+                    self.open = false;
+                    return Some(level.clone());
+                }
+            },
+            _ => {},
+        }
+        None
+    }
+
+    pub fn draw(&self, canvas: &mut WindowCanvas, progress: &Progress) {
+        let mut lines = vec!("Levels (Up/Down to choose, Enter to play, L to close)".to_string());
+        if self.levels.is_empty() {
+            lines.push("No levels found; open one with the play command first".to_string());
+        }
+        let start = (self.selected + 1).saturating_sub(VISIBLE_LINES);
+        for (i, level) in self.levels.iter().enumerate().skip(start).take(VISIBLE_LINES) {
+            let name = Path::new(level).file_name().map_or(level.clone(), |name| name.to_string_lossy().to_string());
+            let best = progress.get(level).map_or("not played yet".to_string(), |record| record.describe());
+            let marker = if i == self.selected { ">" } else { " " };
+            lines.push(format!("{} {}  {}", marker, name, best));
+        }
+        let (width, _) = canvas.output_size().expect("Failed to get screen size");
+        let height = (lines.len() as i32 * LINE_HEIGHT + 8) as u32;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(BACKGROUND_COLOR);
+        canvas.fill_rect(Rect::new(0, 0, width, height)).expect("Failed to draw level list");
+        canvas.set_blend_mode(BlendMode::None);
+        for (i, line) in lines.iter().enumerate() {
+            canvas.draw_text(line, (8, 4 + i as i32 * LINE_HEIGHT), TEXT_SCALE, TEXT_COLOR);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Standard Sokoban solution notation: one letter per move,
+//! lowercase for a plain step and uppercase for a move that pushes a block.
+//! A run of the same move may be abbreviated with a count, as in "3r".
+
+use {player_motion, DeltaFrame, UndoStack, WorldMap};
+
+fn direction(c: char) -> Option<(i32, i32)> {
+    match c.to_ascii_lowercase() {
+        'l' => Some((-1, 0)),
+        'u' => Some((0, -1)),
+        'r' => Some((1, 0)),
+        'd' => Some((0, 1)),
+        _ => None,
+    }
+}
+
+pub fn move_char((dx, dy): (i32, i32), push: bool) -> char {
+    let c = match (dx, dy) {
+        (-1, 0) => 'l',
+        (0, -1) => 'u',
+        (1, 0) => 'r',
+        (0, 1) => 'd',
+        _ => panic!("Tried to write a move of {:?} in LURD", (dx, dy)),
+    };
+    if push { c.to_ascii_uppercase() } else { c }
+}
+
+/// Expand run lengths and strip whitespace, leaving one char per move
+pub fn decode(text: &str) -> Result<Vec<char>, String> {
+    let mut moves = vec!();
+    let mut count: Option<usize> = None;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if let Some(digit) = c.to_digit(10) {
+            count = Some(count.unwrap_or(0) * 10 + digit as usize);
+            continue;
+        }
+        if direction(c).is_none() {
+            return Err(format!("{:?} is not a LURD move", c));
+        }
+        for _ in 0..count.unwrap_or(1) {
+            moves.push(c);
+        }
+        count = None;
+    }
+    if count.is_some() {
+        return Err("Solution ends with a run length but no move".to_string());
+    }
+    Ok(moves)
+}
+
+// Each single step the player has taken, and whether it pushed anything
+// Anything that moved the player other than a single step (like teleporting) is left out.
+fn player_moves(undo_stack: &UndoStack, player_id: usize) -> impl Iterator<Item = ((i32, i32), bool)> + '_ {
+    undo_stack.history()
+        .filter_map(move |motions| player_motion(motions, player_id))
+        .filter(|&((dx, dy), _)| dx.abs() + dy.abs() == 1)
+}
+
+/// The move history on the undo stack, written as LURD
+pub fn export(undo_stack: &UndoStack, player_id: usize) -> String {
+    player_moves(undo_stack, player_id)
+        .map(|(dir, pushed)| move_char(dir, pushed))
+        .collect()
+}
+
+/// How many moves export would write, for counting moves as they're made
+pub fn move_count(undo_stack: &UndoStack, player_id: usize) -> usize {
+    player_moves(undo_stack, player_id).count()
+}
+
+#[derive(Debug)]
+pub enum StepError {
+    Blocked,
+    Caught,
+    ExpectedPush,
+    UnexpectedPush,
+}
+
+impl std::fmt::Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let reason = match self {
+            StepError::Blocked => "the way is blocked",
+            StepError::Caught => "the player has already been caught",
+            StepError::ExpectedPush => "it's written as a push, but nothing was pushed",
+            StepError::UnexpectedPush => "it's written as a walk, but it pushes something",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Make one LURD move on the map, recording it in the delta frame
+/// A move whose push/no-push case doesn't match what actually happened is illegal,
+/// and is undone before returning.
+pub fn step(map: &mut WorldMap, c: char, delta: &mut DeltaFrame) -> Result<(), StepError> {
+    let dir = direction(c).expect("step() expects a decoded LURD move");
+    if map.caught() {
+        return Err(StepError::Caught);
+    }
+    let mut frame = DeltaFrame::new();
+    if !map.take_turn(dir, &mut frame) {
+        return Err(StepError::Blocked);
+    }
+    let pushed = frame.player_motion(map.get_player_id()).is_some_and(|(_, pushed)| pushed);
+    if pushed != c.is_ascii_uppercase() {
+        frame.revert(map);
+        return Err(if pushed { StepError::UnexpectedPush } else { StepError::ExpectedPush });
+    }
+    delta.deltas.append(&mut frame.deltas);
+    Ok(())
+}
+
+pub struct Verification {
+    pub moves: usize,
+    pub pushes: usize,
+    pub solved: bool,
+    // The (0-based) move that couldn't be made, and why
+    pub illegal: Option<(usize, StepError)>,
+}
+
+/// Write a list of moves in LURD, by playing them to see which ones push
+/// The map is back where it started when this returns.
+pub fn encode(map: &mut WorldMap, moves: &[(i32, i32)]) -> String {
+    // A blocked move still turns the player, without a frame to revert
+    let facing = map.get_player_facing();
+    let mut frames = vec!();
+    let mut text = String::new();
+    for &dir in moves.iter() {
+        let mut frame = DeltaFrame::new();
+        map.take_turn(dir, &mut frame);
+        let pushed = frame.player_motion(map.get_player_id()).is_some_and(|(_, pushed)| pushed);
+        text.push(move_char(dir, pushed));
+        frames.push(frame);
+    }
+    for mut frame in frames.into_iter().rev() {
+        frame.revert(map);
+    }
+    map.set_facing(facing);
+    text
+}
+
+/// Replay a solution from the map's current state and report how far it got
+/// The map is left in whatever state the replay reached.
+pub fn verify(map: &mut WorldMap, text: &str) -> Result<Verification, String> {
+    let moves = decode(text)?;
+    let mut result = Verification {
+        moves: 0,
+        pushes: 0,
+        solved: false,
+        illegal: None,
+    };
+    for (i, c) in moves.into_iter().enumerate() {
+        let mut delta = DeltaFrame::new();
+        if let Err(e) = step(map, c, &mut delta) {
+            result.illegal = Some((i, e));
+            break;
+        }
+        result.moves += 1;
+        if c.is_ascii_uppercase() {
+            result.pushes += 1;
+        }
+    }
+    result.solved = map.solved();
+    Ok(result)
+}
+
+/// Play a solution on the map as if the player had typed it, one undo frame per move
+/// Stops at the first illegal move; returns how many moves were made.
+pub fn import(map: &mut WorldMap, undo_stack: &mut UndoStack, text: &str) -> Result<usize, String> {
+    let moves = decode(text)?;
+    let count = moves.len();
+    for (i, c) in moves.into_iter().enumerate() {
+        let mut delta = DeltaFrame::new();
+        if let Err(e) = step(map, c, &mut delta) {
+            return Err(format!("Move {} ({}) is illegal: {}", i + 1, c, e));
+        }
+        undo_stack.push(delta);
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use level;
+
+    const LEVEL: &str = "#######\n#@ $ .#\n#     #\n#######\n";
+
+    #[test]
+    fn decode_expands_run_lengths() {
+        assert_eq!(decode("2r U\nl").unwrap(), vec!('r', 'r', 'U', 'l'));
+        assert!(decode("3").is_err());
+        assert!(decode("rx").is_err());
+    }
+
+    #[test]
+    fn encode_marks_pushes_and_leaves_the_map_alone() {
+        let mut map = level::parse_level(LEVEL).unwrap();
+        assert_eq!(encode(&mut map, &[(1, 0), (1, 0), (1, 0)]), "rRR");
+        assert_eq!(map.get_player_pos(), (1, 1));
+        assert!(!map.solved());
+        // Bumping into the wall still turns the player, which has to be put back too
+        let facing = map.get_player_facing();
+        encode(&mut map, &[(0, -1)]);
+        assert_eq!(map.get_player_facing(), facing);
+    }
+
+    #[test]
+    fn verify_checks_pushes_and_solving() {
+        let mut map = level::parse_level(LEVEL).unwrap();
+        let result = verify(&mut map, "rRR").unwrap();
+        assert!(result.solved && result.illegal.is_none());
+        assert_eq!((result.moves, result.pushes), (3, 2));
+
+        let mut map = level::parse_level(LEVEL).unwrap();
+        let result = verify(&mut map, "rrR").unwrap();
+        assert!(!result.solved);
+        assert!(matches!(result.illegal, Some((1, StepError::UnexpectedPush))));
+    }
+
+    #[test]
+    fn export_survives_a_full_undo_stack() {
+        let mut map = level::parse_level(LEVEL).unwrap();
+        let mut undo_stack = UndoStack::new(2);
+        import(&mut map, &mut undo_stack, "rdrluRR").unwrap();
+        assert_eq!(export(&undo_stack, map.get_player_id()), "rdrluRR");
+        undo_stack.pop(&mut map);
+        assert_eq!(export(&undo_stack, map.get_player_id()), "rdrluR");
+    }
+}
@@ -1,12 +1,37 @@
 extern crate sdl2;
 
+mod cli;
+mod console;
+mod font;
+mod generate;
+mod headless;
+mod level;
+mod level_select;
+mod lurd;
+mod overlay;
+mod path;
+mod progress;
+mod solver;
+mod sound;
+mod sprite;
+mod validate;
+
+use console::Console;
+use level_select::LevelSelect;
+use progress::Progress;
+use font::DrawText;
+use sound::{SoundEvent, Sounds};
+use sprite::{Paint, Sprite, TileAtlas};
+
 use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 use sdl2::rect::Rect;
-use sdl2::render::WindowCanvas;
+use sdl2::video::FullscreenType;
 
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use std::cmp;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
 
@@ -21,14 +46,41 @@ fn new_id() -> usize {
     return id;
 }
 
+// Initial window size; the window can be resized freely afterwards
 const SCREEN_WIDTH: u32 = 800;
 const SCREEN_HEIGHT: u32 = 600;
 
+// Strip along the bottom of the window kept free for the HUD
+const HUD_HEIGHT: u32 = 32;
+
+// Game logic runs at a fixed rate, however fast we happen to be rendering
+const TICKS_PER_SECOND: u32 = 60;
+// Never try to catch up on more than this much real time at once (e.g. after the window was dragged)
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
 const ANIMATION_LENGTH: u32 = 6;
+const MAX_UNDO_DEPTH: usize = 1000;
 const UNDO_COOLDOWN_MAX: u32 = 6;
 
+// A blocked move nudges the stuck objects this far (in tiles) toward the obstacle and back
+const BUMP_LENGTH: u32 = 8;
+const BUMP_DISTANCE: f32 = 0.15;
+
+// How long (in ticks) messages to the player stay on screen
+const MESSAGE_LENGTH: u32 = 120;
+
+// How often to check whether the level file has been edited
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+const TEXT_COLOR: Color = Color { r: 240, g: 240, b: 240, a: 0xff };
+
+// Default tile size, used when nothing needs to be fit to a window
 const MESH: i32 = 40;
 
+const BACKGROUND_COLOR: Color = Color { r: 150, g: 100, b: 150, a: 0xff };
+// Fills the parts of the window that the map doesn't cover
+const LETTERBOX_COLOR: Color = Color { r: 40, g: 30, b: 40, a: 0xff };
+
 /// Abstract Type for "things that live in the world map"
 /// It is always implemented indirectly, via Layers.
 /// Every game object implements exactly one Layer type.
@@ -38,18 +90,174 @@ trait GameObject {
     fn get_id(&self) -> usize;
     fn get_pos(&self) -> (i32, i32);
     fn get_layer(&self) -> Layer;
+    fn kind(&self) -> ObjectKind;
     fn pushable(&self) -> bool;
     fn shift_pos(&mut self, (i32, i32), &mut DeltaFrame);
     fn set_pos(&mut self, (i32, i32));
-    fn draw(&self, &mut WindowCanvas);
+    fn draw(&self, &mut dyn Paint, &DrawContext);
+    
+    // How many cells the object covers, counting right and down from get_pos
+    fn size(&self) -> (i32, i32) {
+        (1, 1)
+    }
+    
+    // Every cell the object covers
+    fn cells(&self) -> Vec<(i32, i32)> {
+        let (x, y) = self.get_pos();
+        let (width, height) = self.size();
+        (0..width).flat_map(|dx| (0..height).map(move |dy| (x + dx, y + dy))).collect()
+    }
 }
 
+/// Objects can be at most this many cells wide or tall
+const MAX_OBJECT_SIZE: i32 = 2;
+
+/// Most objects the player can push at once, unless a level says otherwise (classic Sokoban is 1)
+const DEFAULT_STRENGTH: Option<usize> = Some(1);
+
+/// How far (in cells, diagonals included) an exploding bomb breaks walls
+const BOMB_RADIUS: i32 = 1;
+
+/// The four ways to step: up, right, down, then left
+const DIRECTIONS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
 impl std::fmt::Debug for GameObject {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "Object at {:?}", self as *const GameObject)
     }
 }
 
+/// Everything an object needs to know to draw itself
+struct DrawContext<'a, 't: 'a> {
+    atlas: Option<&'a TileAtlas<'t>>,
+    // Size of a tile on screen, and where the top left corner of the map goes
+    mesh: i32,
+    origin: (i32, i32),
+    // How far (in tiles) objects are drawn from where they really are, by id
+    offsets: HashMap<usize, (f32, f32)>,
+}
+
+impl<'a, 't> DrawContext<'a, 't> {
+    fn new(atlas: Option<&'a TileAtlas<'t>>) -> DrawContext<'a, 't> {
+        DrawContext {
+            atlas,
+            mesh: MESH,
+            origin: (0, 0),
+            offsets: HashMap::new(),
+        }
+    }
+    
+    // Use the biggest tiles that fit a (width x height) map inside area, and center it there
+    fn fit(&mut self, (width, height): (i32, i32), area: Rect) {
+        self.mesh = cmp::max(1, cmp::min(area.width() as i32 / width, area.height() as i32 / height));
+        self.origin = (
+            area.x() + (area.width() as i32 - self.mesh * width) / 2,
+            area.y() + (area.height() as i32 - self.mesh * height) / 2,
+        );
+    }
+    
+    // The part of the screen covered by a (width x height) map
+    fn map_rect(&self, (width, height): (i32, i32)) -> Rect {
+        Rect::new(self.origin.0, self.origin.1, (self.mesh * width) as u32, (self.mesh * height) as u32)
+    }
+    
+    // Which map cell is under the screen point (x, y)
+    fn cell_at(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        (
+            (x - self.origin.0).div_euclid(self.mesh),
+            (y - self.origin.1).div_euclid(self.mesh),
+        )
+    }
+    
+    // Where on the screen an object at (x, y) should be drawn
+    fn tile_rect(&self, id: usize, (x, y): (i32, i32)) -> Rect {
+        self.span_rect(id, (x, y), (1, 1))
+    }
+    
+    // Same as tile_rect, for an object covering (width x height) cells
+    fn span_rect(&self, id: usize, pos: (i32, i32), size: (i32, i32)) -> Rect {
+        let offset = self.offsets.get(&id).cloned().unwrap_or((0.0, 0.0));
+        self.rect_at(pos, offset, size)
+    }
+    
+    // Where on the screen the cell at (x, y) is, whatever may be moving through it
+    fn cell_rect(&self, pos: (i32, i32)) -> Rect {
+        self.rect_at(pos, (0.0, 0.0), (1, 1))
+    }
+    
+    fn rect_at(&self, (x, y): (i32, i32), (ox, oy): (f32, f32), (width, height): (i32, i32)) -> Rect {
+        Rect::new(
+            self.origin.0 + ((x as f32 + ox) * self.mesh as f32).round() as i32,
+            self.origin.1 + ((y as f32 + oy) * self.mesh as f32).round() as i32,
+            (self.mesh * width) as u32,
+            (self.mesh * height) as u32,
+        )
+    }
+}
+
+/// What an object is, for code that needs to reason about the level
+/// (win detection, converting to and from level files, ...)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum ObjectKind {
+    Player,
+    Block(Tint),
+    Wall,
+    Goal(Tint),
+    Key(Tint),
+    Door(Tint),
+    Conveyor((i32, i32)),
+    BreakableWall,
+    Bomb,
+    Switch,
+    Enemy(EnemyBehavior),
+}
+
+/// How an enemy decides where to step after each of the player's moves
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum EnemyBehavior {
+    // Take the first step of the shortest walk to the player
+    Chase,
+    // Walk this way until blocked, then turn around
+    Patrol((i32, i32)),
+}
+
+/// Blocks and goals can come in colors, and a colored goal needs a block of its own color
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Tint {
+    Plain,
+    Red,
+    Green,
+    Blue,
+    Yellow,
+}
+
+impl Tint {
+    // What to multiply an object's colors by, the same way SDL's texture color mod does
+    fn modulation(&self) -> Color {
+        match self {
+            Tint::Plain => Color::RGB(255, 255, 255),
+            Tint::Red => Color::RGB(255, 100, 90),
+            Tint::Green => Color::RGB(110, 230, 110),
+            Tint::Blue => Color::RGB(110, 140, 255),
+            Tint::Yellow => Color::RGB(255, 230, 80),
+        }
+    }
+    
+    fn apply(&self, base: Color) -> Color {
+        let m = self.modulation();
+        let channel = |c: u8, m: u8| (c as u32 * m as u32 / 255) as u8;
+        Color::RGB(channel(base.r, m.r), channel(base.g, m.g), channel(base.b, m.b))
+    }
+}
+
+/// Feedback for a move that couldn't happen
+/// This is purely visual: nothing actually moved, so there's nothing to undo
+struct Bump {
+    ids: Vec<usize>,
+    dir: (i32, i32),
+    ticks_left: u32,
+}
+
 /// Keeps track of whether the game is ready to receive new input
 /// A state of Anim(n) indicates there are n frames of animation left
 /// This simple model only makes sense for a discrete-time puzzle game
@@ -62,6 +270,8 @@ struct Player {
     id: usize,
     x: i32,
     y: i32,
+    // The direction the player last tried to move in
+    facing: (i32, i32),
     color: Color,
 }
 
@@ -71,6 +281,7 @@ impl Player {
             id: new_id(),
             x,
             y,
+            facing: (0, 1),
             color: Color::RGB(230, 240, 200),
         }
     }
@@ -85,6 +296,10 @@ impl GameObject for Player {
         Layer::Solid
     }
     
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Player
+    }
+    
     fn get_pos(&self) -> (i32, i32) {
         (self.x, self.y)
     }
@@ -112,9 +327,13 @@ impl GameObject for Player {
         self.y = y;
     }
     
-    fn draw(&self, canvas: &mut WindowCanvas) {
-        canvas.set_draw_color(self.color);
-        canvas.fill_rect(Rect::new(MESH*self.x, MESH*self.y, MESH as u32, MESH as u32)).expect("Failed to draw Player rect");
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        sprite::draw_tile(canvas, context.atlas, Sprite::Player(sprite::direction_frame(self.facing)), self.color, rect);
+        if context.atlas.is_none() {
+            canvas.set_draw_color(Color::RGB(40, 40, 40));
+            canvas.fill_rect(sprite::facing_mark(rect, self.facing)).expect("Failed to draw player facing");
+        }
     }
 }
 
@@ -122,7 +341,11 @@ struct Block {
     id: usize,
     x: i32,
     y: i32,
+    size: (i32, i32),
     pushable: bool,
+    // Only walls can be breakable
+    breakable: bool,
+    tint: Tint,
     color: Color,
 }
 
@@ -132,20 +355,59 @@ impl Block {
             id: new_id(),
             x,
             y,
+            size: (1, 1),
             pushable: true,
+            breakable: false,
+            tint: Tint::Plain,
             color: Color::RGB(200, 180, 100),
         }
     }
     
+    fn new_tinted_block(x: i32, y: i32, tint: Tint) -> Block {
+        if tint == Tint::Plain {
+            return Block::new_block(x, y);
+        }
+        Block {
+            id: new_id(),
+            x,
+            y,
+            size: (1, 1),
+            pushable: true,
+            breakable: false,
+            tint,
+            color: tint.apply(Color::RGB(230, 230, 230)),
+        }
+    }
+    
     fn new_wall(x: i32, y: i32) -> Block {
         Block {
             id: new_id(),
             x,
             y,
+            size: (1, 1),
             pushable: false,
+            breakable: false,
+            tint: Tint::Plain,
             color: Color::RGB(80, 20, 50),
         }
     }
+    
+    // A wall that bombs can destroy
+    fn new_breakable_wall(x: i32, y: i32) -> Block {
+        Block {
+            breakable: true,
+            color: Color::RGB(140, 80, 90),
+            ..Block::new_wall(x, y)
+        }
+    }
+    
+    // A block covering (width x height) cells, with (x, y) as its top left corner
+    fn new_big_block(x: i32, y: i32, size: (i32, i32), tint: Tint) -> Block {
+        Block {
+            size,
+            ..Block::new_tinted_block(x, y, tint)
+        }
+    }
 }
 
 impl GameObject for Block {
@@ -157,6 +419,16 @@ impl GameObject for Block {
         Layer::Solid
     }
 
+    fn kind(&self) -> ObjectKind {
+        if self.pushable {
+            ObjectKind::Block(self.tint)
+        } else if self.breakable {
+            ObjectKind::BreakableWall
+        } else {
+            ObjectKind::Wall
+        }
+    }
+
     fn get_pos(&self) -> (i32, i32) {
         (self.x, self.y)
     }
@@ -184,9 +456,518 @@ impl GameObject for Block {
         self.y = y;
     }
     
-    fn draw(&self, canvas: &mut WindowCanvas) {
-        canvas.set_draw_color(self.color);
-        canvas.fill_rect(Rect::new(MESH*self.x, MESH*self.y, MESH as u32, MESH as u32)).expect("Failed to draw Player rect");
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let sprite = if self.pushable {
+            Sprite::Block(self.tint)
+        } else if self.breakable {
+            Sprite::BreakableWall
+        } else {
+            Sprite::Wall
+        };
+        let rect = context.span_rect(self.id, (self.x, self.y), self.size);
+        sprite::draw_tile(canvas, context.atlas, sprite, self.color, rect);
+    }
+    
+    fn size(&self) -> (i32, i32) {
+        self.size
+    }
+}
+
+/// A target square for blocks, living on the Floor layer
+struct Goal {
+    id: usize,
+    x: i32,
+    y: i32,
+    tint: Tint,
+    color: Color,
+}
+
+impl Goal {
+    fn new(x: i32, y: i32, tint: Tint) -> Goal {
+        Goal {
+            id: new_id(),
+            x,
+            y,
+            tint,
+            color: if tint == Tint::Plain { Color::RGB(120, 200, 140) } else { tint.apply(Color::RGB(190, 190, 190)) },
+        }
+    }
+}
+
+impl GameObject for Goal {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_layer(&self) -> Layer {
+        Layer::Floor
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Goal(self.tint)
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    fn pushable(&self) -> bool {
+        false
+    }
+    
+    fn shift_pos(&mut self, (dx, dy): (i32, i32), delta_frame: &mut DeltaFrame) {
+        self.x += dx;
+        self.y += dy;
+        delta_frame.push(Box::new(MotionDelta {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            layer: self.get_layer(),
+            dx,
+            dy,
+        }));
+    }
+    
+    fn set_pos(&mut self, (x, y): (i32, i32)) {
+        self.x = x;
+        self.y = y;
+    }
+    
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        sprite::draw_tile(canvas, context.atlas, Sprite::Goal(self.tint), self.color, rect);
+    }
+}
+
+/// Picked up by the player walking over it, and used up opening a Door of the same tint
+struct Key {
+    id: usize,
+    x: i32,
+    y: i32,
+    tint: Tint,
+    color: Color,
+}
+
+impl Key {
+    fn new(x: i32, y: i32, tint: Tint) -> Key {
+        Key {
+            id: new_id(),
+            x,
+            y,
+            tint,
+            color: tint.apply(Color::RGB(250, 220, 120)),
+        }
+    }
+}
+
+impl GameObject for Key {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_layer(&self) -> Layer {
+        Layer::Floor
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Key(self.tint)
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    fn pushable(&self) -> bool {
+        false
+    }
+    
+    fn shift_pos(&mut self, (dx, dy): (i32, i32), delta_frame: &mut DeltaFrame) {
+        self.x += dx;
+        self.y += dy;
+        delta_frame.push(Box::new(MotionDelta {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            layer: self.get_layer(),
+            dx,
+            dy,
+        }));
+    }
+    
+    fn set_pos(&mut self, (x, y): (i32, i32)) {
+        self.x = x;
+        self.y = y;
+    }
+    
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        // Without a sprite, a key is a small square in the middle of its cell
+        let rect = if context.atlas.is_some() {
+            rect
+        } else {
+            let size = rect.width() / 3;
+            Rect::new(rect.x() + size as i32, rect.y() + size as i32, size, size)
+        };
+        sprite::draw_tile(canvas, context.atlas, Sprite::Key(self.tint), self.color, rect);
+    }
+}
+
+/// A wall that disappears when the player walks into it holding a Key of the same tint
+struct Door {
+    id: usize,
+    x: i32,
+    y: i32,
+    tint: Tint,
+    color: Color,
+}
+
+impl Door {
+    fn new(x: i32, y: i32, tint: Tint) -> Door {
+        Door {
+            id: new_id(),
+            x,
+            y,
+            tint,
+            color: tint.apply(Color::RGB(150, 110, 60)),
+        }
+    }
+}
+
+impl GameObject for Door {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_layer(&self) -> Layer {
+        Layer::Solid
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Door(self.tint)
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    fn pushable(&self) -> bool {
+        false
+    }
+    
+    fn shift_pos(&mut self, (dx, dy): (i32, i32), delta_frame: &mut DeltaFrame) {
+        self.x += dx;
+        self.y += dy;
+        delta_frame.push(Box::new(MotionDelta {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            layer: self.get_layer(),
+            dx,
+            dy,
+        }));
+    }
+    
+    fn set_pos(&mut self, (x, y): (i32, i32)) {
+        self.x = x;
+        self.y = y;
+    }
+    
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        sprite::draw_tile(canvas, context.atlas, Sprite::Door(self.tint), self.color, rect);
+    }
+}
+
+/// A floor tile that carries whatever stands on it one cell along at the end of each turn
+struct Conveyor {
+    id: usize,
+    x: i32,
+    y: i32,
+    dir: (i32, i32),
+    color: Color,
+}
+
+impl Conveyor {
+    fn new(x: i32, y: i32, dir: (i32, i32)) -> Conveyor {
+        Conveyor {
+            id: new_id(),
+            x,
+            y,
+            dir,
+            color: Color::RGB(90, 90, 110),
+        }
+    }
+}
+
+impl GameObject for Conveyor {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_layer(&self) -> Layer {
+        Layer::Floor
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Conveyor(self.dir)
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    fn pushable(&self) -> bool {
+        false
+    }
+    
+    fn shift_pos(&mut self, (dx, dy): (i32, i32), delta_frame: &mut DeltaFrame) {
+        self.x += dx;
+        self.y += dy;
+        delta_frame.push(Box::new(MotionDelta {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            layer: self.get_layer(),
+            dx,
+            dy,
+        }));
+    }
+    
+    fn set_pos(&mut self, (x, y): (i32, i32)) {
+        self.x = x;
+        self.y = y;
+    }
+    
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        sprite::draw_tile(canvas, context.atlas, Sprite::Conveyor(self.dir), self.color, rect);
+        if context.atlas.is_none() {
+            // Without a sprite, mark the edge the belt is heading towards
+            let size = rect.width() / 4;
+            let (dx, dy) = self.dir;
+            let edge = Rect::new(
+                rect.x() + if dx > 0 { (rect.width() - size) as i32 } else { 0 },
+                rect.y() + if dy > 0 { (rect.height() - size) as i32 } else { 0 },
+                if dx == 0 { rect.width() } else { size },
+                if dy == 0 { rect.height() } else { size },
+            );
+            canvas.set_draw_color(Color::RGB(200, 200, 60));
+            canvas.fill_rect(edge).expect("Failed to draw conveyor");
+        }
+    }
+}
+
+/// A pushable block that explodes when shoved into a breakable wall, or when a Switch is pressed
+struct Bomb {
+    id: usize,
+    x: i32,
+    y: i32,
+    color: Color,
+}
+
+impl Bomb {
+    fn new(x: i32, y: i32) -> Bomb {
+        Bomb {
+            id: new_id(),
+            x,
+            y,
+            color: Color::RGB(50, 50, 60),
+        }
+    }
+}
+
+impl GameObject for Bomb {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_layer(&self) -> Layer {
+        Layer::Solid
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Bomb
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    fn pushable(&self) -> bool {
+        true
+    }
+    
+    fn shift_pos(&mut self, (dx, dy): (i32, i32), delta_frame: &mut DeltaFrame) {
+        self.x += dx;
+        self.y += dy;
+        delta_frame.push(Box::new(MotionDelta {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            layer: self.get_layer(),
+            dx,
+            dy,
+        }));
+    }
+    
+    fn set_pos(&mut self, (x, y): (i32, i32)) {
+        self.x = x;
+        self.y = y;
+    }
+    
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        sprite::draw_tile(canvas, context.atlas, Sprite::Bomb, self.color, rect);
+    }
+}
+
+/// A floor tile that sets off every bomb in the level while something stands on it
+struct Switch {
+    id: usize,
+    x: i32,
+    y: i32,
+    color: Color,
+}
+
+impl Switch {
+    fn new(x: i32, y: i32) -> Switch {
+        Switch {
+            id: new_id(),
+            x,
+            y,
+            color: Color::RGB(200, 70, 60),
+        }
+    }
+}
+
+impl GameObject for Switch {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_layer(&self) -> Layer {
+        Layer::Floor
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Switch
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    fn pushable(&self) -> bool {
+        false
+    }
+    
+    fn shift_pos(&mut self, (dx, dy): (i32, i32), delta_frame: &mut DeltaFrame) {
+        self.x += dx;
+        self.y += dy;
+        delta_frame.push(Box::new(MotionDelta {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            layer: self.get_layer(),
+            dx,
+            dy,
+        }));
+    }
+    
+    fn set_pos(&mut self, (x, y): (i32, i32)) {
+        self.x = x;
+        self.y = y;
+    }
+    
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        // Without a sprite, a switch is a button in the middle of its cell
+        let rect = if context.atlas.is_some() {
+            rect
+        } else {
+            let size = rect.width() / 2;
+            Rect::from_center(rect.center(), size, size)
+        };
+        sprite::draw_tile(canvas, context.atlas, Sprite::Switch, self.color, rect);
+    }
+}
+
+/// Something that moves on its own after every player move, and catches the player by touching them
+struct Enemy {
+    id: usize,
+    x: i32,
+    y: i32,
+    behavior: EnemyBehavior,
+    color: Color,
+}
+
+impl Enemy {
+    fn new(x: i32, y: i32, behavior: EnemyBehavior) -> Enemy {
+        Enemy::with_id(new_id(), x, y, behavior)
+    }
+    
+    // Enemies are rebuilt every turn, keeping their id so animation and undo can still find them
+    fn with_id(id: usize, x: i32, y: i32, behavior: EnemyBehavior) -> Enemy {
+        Enemy {
+            id,
+            x,
+            y,
+            behavior,
+            color: Color::RGB(220, 60, 70),
+        }
+    }
+}
+
+impl GameObject for Enemy {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_layer(&self) -> Layer {
+        Layer::Solid
+    }
+
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Enemy(self.behavior)
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    fn pushable(&self) -> bool {
+        false
+    }
+    
+    fn shift_pos(&mut self, (dx, dy): (i32, i32), delta_frame: &mut DeltaFrame) {
+        self.x += dx;
+        self.y += dy;
+        delta_frame.push(Box::new(MotionDelta {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            layer: self.get_layer(),
+            dx,
+            dy,
+        }));
+    }
+    
+    fn set_pos(&mut self, (x, y): (i32, i32)) {
+        self.x = x;
+        self.y = y;
+    }
+    
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        let rect = context.tile_rect(self.id, (self.x, self.y));
+        let frame = match self.behavior {
+            EnemyBehavior::Chase => 0,
+            EnemyBehavior::Patrol(dir) => sprite::direction_frame(dir) + 1,
+        };
+        sprite::draw_tile(canvas, context.atlas, Sprite::Enemy(frame), self.color, rect);
+        if let (None, EnemyBehavior::Patrol(dir)) = (context.atlas, self.behavior) {
+            canvas.set_draw_color(Color::RGB(40, 40, 40));
+            canvas.fill_rect(sprite::facing_mark(rect, dir)).expect("Failed to draw enemy facing");
+        }
     }
 }
 
@@ -194,6 +975,19 @@ impl GameObject for Block {
 /// Deltas are created automatically, placed on a stack, and then reverted when you undo
 trait Delta {
     fn revert(&mut self, &mut WorldMap);
+    
+    // One line saying what changed, for the debug overlay
+    fn describe(&self) -> String;
+    
+    // If this delta moved an object, which object and by how much
+    fn motion(&self) -> Option<(usize, (i32, i32))> {
+        None
+    }
+    
+    // Objects moved by the level rather than by the player, like conveyor belts
+    fn side_motions(&self) -> Vec<(usize, (i32, i32))> {
+        vec!()
+    }
 }
 
 /// Store the current (post-move) location of an object
@@ -214,6 +1008,14 @@ impl Delta for MotionDelta {
         object.shift_pos((-self.dx, -self.dy), &mut redo);
         map.put_quiet(object);
     }
+    
+    fn motion(&self) -> Option<(usize, (i32, i32))> {
+        Some((self.id, (self.dx, self.dy)))
+    }
+    
+    fn describe(&self) -> String {
+        format!("Move #{} by {:?} to {:?}", self.id, (self.dx, self.dy), (self.x, self.y))
+    }
 }
 
 /// Move ownership of object from game map to the undo stack
@@ -236,6 +1038,104 @@ impl Delta for DeletionDelta {
             map.put_quiet(object);
         }
     }
+    
+    fn describe(&self) -> String {
+        match self.object {
+            Some(ref object) => format!("Delete #{} {:?} at {:?}", object.get_id(), object.kind(), object.get_pos()),
+            None => "Delete (already undone)".to_string(),
+        }
+    }
+}
+
+/// Store which way the player was facing before a turn
+struct FacingDelta {
+    facing: (i32, i32),
+}
+
+impl Delta for FacingDelta {
+    fn revert(&mut self, map: &mut WorldMap) {
+        unsafe {
+            (*map.player).facing = self.facing;
+        }
+    }
+    
+    fn describe(&self) -> String {
+        format!("Turn player from {:?}", self.facing)
+    }
+}
+
+/// Store an enemy's current (post-turn) location, and where it was and what it was doing before
+struct EnemyDelta {
+    id: usize,
+    x: i32,
+    y: i32,
+    from: (i32, i32),
+    behavior: EnemyBehavior,
+}
+
+impl Delta for EnemyDelta {
+    fn revert(&mut self, map: &mut WorldMap) {
+        map.take_id(self.x, self.y, &Layer::Solid, self.id).unwrap();
+        let (x, y) = self.from;
+        map.put_quiet(Box::new(Enemy::with_id(self.id, x, y, self.behavior)));
+    }
+    
+    fn describe(&self) -> String {
+        format!("Enemy #{} from {:?} to {:?}", self.id, self.from, (self.x, self.y))
+    }
+    
+    // Enemies move by themselves, so these never count as the player pushing them
+    fn side_motions(&self) -> Vec<(usize, (i32, i32))> {
+        let (x, y) = self.from;
+        if (self.x, self.y) == (x, y) {
+            vec!()
+        } else {
+            vec!((self.id, (self.x - x, self.y - y)))
+        }
+    }
+}
+
+/// Everything the conveyor belts did at the end of a turn
+/// Keeping it in its own frame means it isn't mistaken for the player pushing something.
+struct ConveyorDelta {
+    frame: DeltaFrame,
+}
+
+impl Delta for ConveyorDelta {
+    fn revert(&mut self, map: &mut WorldMap) {
+        self.frame.revert(map);
+    }
+    
+    fn describe(&self) -> String {
+        let inner: Vec<String> = self.frame.deltas.iter().map(|delta| delta.describe()).collect();
+        format!("Conveyors: {}", inner.join("; "))
+    }
+    
+    fn side_motions(&self) -> Vec<(usize, (i32, i32))> {
+        self.frame.motions()
+    }
+}
+
+/// A key entering or leaving the player's inventory
+struct InventoryDelta {
+    tint: Tint,
+    gained: bool,
+}
+
+impl Delta for InventoryDelta {
+    fn revert(&mut self, map: &mut WorldMap) {
+        if self.gained {
+            if let Some(index) = map.inventory.iter().position(|&tint| tint == self.tint) {
+                map.inventory.remove(index);
+            }
+        } else {
+            map.inventory.push(self.tint);
+        }
+    }
+    
+    fn describe(&self) -> String {
+        format!("{} {:?} key", if self.gained { "Pick up" } else { "Use up" }, self.tint)
+    }
 }
 
 struct CreationDelta {
@@ -259,6 +1159,10 @@ impl Delta for CreationDelta {
         let (x, y) = self.pos;
         map.take(x, y, &self.layer);
     }
+    
+    fn describe(&self) -> String {
+        format!("Create #{} on {:?} layer at {:?}", self.id, self.layer, self.pos)
+    }
 }
 
 /// Collection of Deltas representing changes in one step of game logic
@@ -273,8 +1177,10 @@ impl DeltaFrame {
         }
     }
 
+    // Later deltas may depend on earlier ones (a door opening before the player walks through it),
+    // so they're undone last to first
     fn revert(&mut self, map: &mut WorldMap) {
-        for delta in self.deltas.iter_mut() {
+        for delta in self.deltas.iter_mut().rev() {
             delta.revert(map);
         }
     }
@@ -286,12 +1192,47 @@ impl DeltaFrame {
     fn trivial(&self) -> bool {
         self.deltas.is_empty()
     }
+    
+    // The direction the player moved in this frame, and whether anything else moved with them
+    fn player_motion(&self, player_id: usize) -> Option<((i32, i32), bool)> {
+        player_motion(&self.own_motions(), player_id)
+    }
+    
+    // Objects moved by the player's move itself, leaving out anything the level moved on its own
+    fn own_motions(&self) -> Vec<(usize, (i32, i32))> {
+        self.deltas.iter().filter_map(|delta| delta.motion()).collect()
+    }
+    
+    // Every object moved in this frame, and which way
+    fn motions(&self) -> Vec<(usize, (i32, i32))> {
+        self.deltas.iter()
+            .flat_map(|delta| delta.motion().into_iter().chain(delta.side_motions()))
+            .collect()
+    }
+}
+
+// The direction the player moved, and whether anything else moved with them
+fn player_motion(motions: &[(usize, (i32, i32))], player_id: usize) -> Option<((i32, i32), bool)> {
+    let mut player_dir = None;
+    let mut pushed = false;
+    for &(id, dir) in motions.iter() {
+        if id == player_id {
+            player_dir = Some(dir);
+        } else {
+            pushed = true;
+        }
+    }
+    player_dir.map(|dir| (dir, pushed))
 }
 
 struct UndoStack {
-    stack: VecDeque<DeltaFrame>,
+    // Each frame remembers whether it should be undone together with the one before it
+    stack: VecDeque<(DeltaFrame, bool)>,
     max_depth: usize,
     size: usize,
+    // What moved in every frame still in effect, oldest first; unlike the stack
+    // this has no depth limit, so a long solution can still be written out in full
+    history: Vec<Vec<(usize, (i32, i32))>>,
 }
 
 impl UndoStack {
@@ -300,27 +1241,58 @@ impl UndoStack {
             stack: VecDeque::with_capacity(max_depth),
             max_depth,
             size: 0,
+            history: vec!(),
         }
     }
     
     fn push(&mut self, delta: DeltaFrame) {
+        self.push_frame(delta, false);
+    }
+    
+    // Push a frame that gets undone along with the previous one, e.g. the steps of a walk
+    fn push_joined(&mut self, delta: DeltaFrame) {
+        self.push_frame(delta, true);
+    }
+    
+    fn push_frame(&mut self, delta: DeltaFrame, joined: bool) {
+        self.history.push(delta.own_motions());
         if self.size == self.max_depth {
             self.stack.pop_back();
-            self.stack.push_front(delta);
+            self.stack.push_front((delta, joined));
         } else {
-            self.stack.push_front(delta);
+            self.stack.push_front((delta, joined));
             self.size += 1;
         }
     }
     
-    fn pop(&mut self, map: &mut WorldMap) {
-        if self.size > 0 {
-            self.stack.pop_front().unwrap().revert(map);
+    // Returns whether there was anything to undo
+    fn pop(&mut self, map: &mut WorldMap) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        loop {
+            let (mut frame, joined) = self.stack.pop_front().unwrap();
+            frame.revert(map);
             self.size -= 1;
+            self.history.pop();
+            if !joined || self.size == 0 {
+                return true;
+            }
         }
     }
+    
+    // Oldest first, so this is the order the moves were made in
+    fn history(&self) -> impl Iterator<Item = &[(usize, (i32, i32))]> {
+        self.history.iter().map(|motions| motions.as_slice())
+    }
+    
+    // The most recent frame, which is the next to be undone
+    fn last(&self) -> Option<&DeltaFrame> {
+        self.stack.front().map(|(frame, _)| frame)
+    }
 }
 
+#[derive(Debug)]
 enum Layer {
     Solid,
     Player,
@@ -353,14 +1325,17 @@ impl MapCell {
         }
     }
     
-    fn draw(&self, canvas: &mut WindowCanvas) {
-        for layer in self.layers.iter() {
-            for object in layer.iter() {
-                object.draw(canvas);
-            }
+    fn draw_layer(&self, index: usize, canvas: &mut dyn Paint, context: &DrawContext) {
+        for object in self.layers[index].iter() {
+            object.draw(canvas, context);
         }
     }
     
+    // Borrow the top object of a layer without being able to change it
+    fn peek(&self, layer: &Layer) -> Option<&dyn GameObject> {
+        self.layers[Layer::index(layer)].last().map(|object| &**object)
+    }
+    
     // Mutably borrow the top object of a layer
     fn view(&mut self, layer: &Layer) -> Option<&mut Box<dyn GameObject>> {
         self.layers[Layer::index(layer)].last_mut()
@@ -434,6 +1409,10 @@ struct WorldMap {
     height: i32,
     map: Vec<Vec<MapCell>>,
     player: *mut Player,
+    // Keys the player is carrying
+    inventory: Vec<Tint>,
+    // Most objects the player can push at once, if there's a limit
+    push_strength: Option<usize>,
 }
 
 impl WorldMap {
@@ -450,6 +1429,8 @@ impl WorldMap {
             height,
             map,
             player,
+            inventory: vec!(),
+            push_strength: DEFAULT_STRENGTH,
         }
     }
     
@@ -465,37 +1446,62 @@ impl WorldMap {
         }
     }
     
-    // NOTE: this (and similar methods later) are predicated on the assumption of "one object per layer per cell"
-    fn move_solid(&mut self, (dx, dy): (i32, i32), delta: &mut DeltaFrame) -> bool{
+    // Everything that would move if the object at start moved in direction (dx, dy), and whether that's allowed
+    // (if not, the chain is the objects that got stuck). Objects are keyed by their top left cell.
+    fn solid_chain(&mut self, start: (i32, i32), (dx, dy): (i32, i32)) -> (HashMap<(i32, i32), usize>, bool) {
         let layer = &Layer::Solid;
         let mut to_move: HashMap<(i32, i32), usize> = HashMap::new();
-        to_move.insert(self.get_player_pos(), self.get_player_id());
+        // Every cell covered by something in to_move
+        let mut moving_cells: HashSet<(i32, i32)> = HashSet::new();
         let mut to_check: Vec<(i32, i32)> = Vec::new();
-        for (point, _) in to_move.iter() {
-            to_check.push(*point);
-        }
-        // For each iteration: to_move is all points that will be moved if successful
-        // to_check is a subset of to_move.
-        while !to_check.is_empty() {
-            let (x, y) = to_check.pop().unwrap();
-            // We've already checked this cell
-            if to_move.contains_key(&(x+dx, y+dy)) {
+        let (x, y) = start;
+        match self.peek(x, y, layer) {
+            Some(object) => {
+                to_move.insert(object.get_pos(), object.get_id());
+                moving_cells.extend(object.cells());
+                to_check.extend(object.cells());
+            },
+            None => return (to_move, false),
+        }
+        // For each iteration: to_check is the cells whose neighbour in direction (dx, dy) hasn't been looked at
+        while let Some((x, y)) = to_check.pop() {
+            // Part of something that's already moving
+            if moving_cells.contains(&(x+dx, y+dy)) {
                 continue;
             }
             // Something is trying to move out of bounds
             if self.invalid(x+dx, y+dy) {
-                return false;
+                return (to_move, false);
             }
-            match self.view(x+dx, y+dy, layer) {
-                Some(ref object) => if object.pushable() {
-                    to_move.insert((x+dx, y+dy), object.get_id());
-                    to_check.push((x+dx, y+dy));
+            match self.peek(x+dx, y+dy, layer) {
+                Some(object) => if object.pushable() {
+                    to_move.insert(object.get_pos(), object.get_id());
+                    moving_cells.extend(object.cells());
+                    to_check.extend(object.cells());
                 } else {
-                    return false;
+                    return (to_move, false);
                 },
                 None => {},
             }
         }
+        (to_move, true)
+    }
+    
+    fn move_solid(&mut self, (dx, dy): (i32, i32), delta: &mut DeltaFrame) -> bool{
+        let player_pos = self.get_player_pos();
+        let strength = self.push_strength;
+        self.push_from(player_pos, (dx, dy), strength, delta).is_some()
+    }
+    
+    // Move the object at start and everything it pushes, returning the ids of whatever moved
+    // Pushing more than max_pushed other objects at once isn't allowed.
+    fn push_from(&mut self, start: (i32, i32), (dx, dy): (i32, i32), max_pushed: Option<usize>, delta: &mut DeltaFrame) -> Option<Vec<usize>> {
+        let layer = &Layer::Solid;
+        let (to_move, legal) = self.solid_chain(start, (dx, dy));
+        if !legal || max_pushed.is_some_and(|max| to_move.len() - 1 > max) {
+            return None;
+        }
+        let moved = to_move.values().cloned().collect();
         // At this point we are sure the move is legal, so we start moving things
         for ((x, y), id) in to_move.into_iter() {
             let mut object = self.take_id(x, y, layer, id).unwrap();
@@ -510,14 +1516,234 @@ impl WorldMap {
         //    }
         //}
         //self.delete(x, y-1, layer, delta);
+        Some(moved)
+    }
+    
+    // Everything that happens when the player tries to take one step
+    // The player turns to face the step even if it turns out to be blocked.
+    fn take_turn(&mut self, (dx, dy): (i32, i32), delta: &mut DeltaFrame) -> bool {
+        let (x, y) = self.get_player_pos();
+        let start = delta.deltas.len();
+        self.unlock((x+dx, y+dy), delta);
+        self.detonate_pushed_bombs((dx, dy), delta);
+        if !self.move_solid((dx, dy), delta) {
+            // Doors and bombs only open the way for a move; if it still can't happen, put them back
+            for mut undone in delta.deltas.drain(start..).rev() {
+                undone.revert(self);
+            }
+            // Turning toward whatever's in the way is just for show, so it doesn't get an undo step
+            self.set_facing((dx, dy));
+            return false;
+        }
+        self.face((dx, dy), delta);
+        self.pick_up_key(delta);
+        self.run_conveyors(delta);
+        self.check_switches(delta);
+        self.move_enemies(delta);
         true
     }
     
-    // Later, restrict the range based on the camera
-    fn draw(&self, canvas: &mut WindowCanvas) {
+    // Every enemy takes one step, in reading order
+    fn move_enemies(&mut self, delta: &mut DeltaFrame) {
+        let mut enemies = vec!();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if let Some(ObjectKind::Enemy(behavior)) = self.peek(x, y, &Layer::Solid).map(|object| object.kind()) {
+                    enemies.push(((x, y), behavior));
+                }
+            }
+        }
+        let player_pos = self.get_player_pos();
+        for ((x, y), old_behavior) in enemies {
+            let free = |map: &WorldMap, (dx, dy): (i32, i32)| {
+                !map.invalid(x+dx, y+dy) && map.peek(x+dx, y+dy, &Layer::Solid).is_none()
+            };
+            let (step, behavior) = match old_behavior {
+                EnemyBehavior::Chase => {
+                    let step = path::chase_step(self, (x, y), player_pos).filter(|&dir| free(self, dir));
+                    (step, old_behavior)
+                },
+                EnemyBehavior::Patrol((dx, dy)) => if free(self, (dx, dy)) {
+                    (Some((dx, dy)), old_behavior)
+                } else if free(self, (-dx, -dy)) {
+                    (Some((-dx, -dy)), EnemyBehavior::Patrol((-dx, -dy)))
+                } else {
+                    (None, EnemyBehavior::Patrol((-dx, -dy)))
+                },
+            };
+            if step.is_none() && behavior == old_behavior {
+                continue;
+            }
+            let (dx, dy) = step.unwrap_or((0, 0));
+            let id = self.take(x, y, &Layer::Solid).unwrap().get_id();
+            self.put_quiet(Box::new(Enemy::with_id(id, x+dx, y+dy, behavior)));
+            delta.push(Box::new(EnemyDelta {
+                id,
+                x: x+dx,
+                y: y+dy,
+                from: (x, y),
+                behavior: old_behavior,
+            }));
+        }
+    }
+    
+    // The player is caught when an enemy is right next to them
+    fn caught(&self) -> bool {
+        let (x, y) = self.get_player_pos();
+        DIRECTIONS.iter().any(|&(dx, dy)| {
+            matches!(self.peek(x+dx, y+dy, &Layer::Solid).map(|object| object.kind()), Some(ObjectKind::Enemy(_)))
+        })
+    }
+    
+    // A bomb that the player would push into a breakable wall goes off instead
+    fn detonate_pushed_bombs(&mut self, (dx, dy): (i32, i32), delta: &mut DeltaFrame) {
+        let player_pos = self.get_player_pos();
+        let (chain, legal) = self.solid_chain(player_pos, (dx, dy));
+        // The player has to be strong enough to shove the bomb in the first place
+        if legal || self.push_strength.is_some_and(|max| chain.len() - 1 > max) {
+            return;
+        }
+        let mut bombs = vec!();
+        for (&(x, y), _) in chain.iter() {
+            let kind = self.peek(x, y, &Layer::Solid).map(|object| object.kind());
+            let ahead = self.peek(x+dx, y+dy, &Layer::Solid).map(|object| object.kind());
+            if kind == Some(ObjectKind::Bomb) && ahead == Some(ObjectKind::BreakableWall) {
+                bombs.push((x, y));
+            }
+        }
+        for pos in bombs {
+            self.explode(pos, delta);
+        }
+    }
+    
+    // Every bomb goes off while anything is standing on a switch
+    fn check_switches(&mut self, delta: &mut DeltaFrame) {
+        let mut pressed = false;
+        let mut bombs = vec!();
+        for x in 0..self.width {
+            for y in 0..self.height {
+                if self.peek(x, y, &Layer::Floor).map(|object| object.kind()) == Some(ObjectKind::Switch) {
+                    pressed |= self.peek(x, y, &Layer::Solid).is_some();
+                }
+                if self.peek(x, y, &Layer::Solid).map(|object| object.kind()) == Some(ObjectKind::Bomb) {
+                    bombs.push((x, y));
+                }
+            }
+        }
+        if pressed {
+            for pos in bombs {
+                self.explode(pos, delta);
+            }
+        }
+    }
+    
+    // Remove the bomb at (x, y) and every breakable wall within BOMB_RADIUS of it
+    fn explode(&mut self, (x, y): (i32, i32), delta: &mut DeltaFrame) {
+        self.delete(x, y, &Layer::Solid, delta);
+        for wx in x - BOMB_RADIUS..=x + BOMB_RADIUS {
+            for wy in y - BOMB_RADIUS..=y + BOMB_RADIUS {
+                if self.peek(wx, wy, &Layer::Solid).map(|object| object.kind()) == Some(ObjectKind::BreakableWall) {
+                    self.delete(wx, wy, &Layer::Solid, delta);
+                }
+            }
+        }
+    }
+    
+    // Each conveyor pushes whatever is on it one cell along
+    // Belts are all read before anything moves, and nothing is carried twice in one turn.
+    fn run_conveyors(&mut self, delta: &mut DeltaFrame) {
+        let mut riders = vec!();
         for x in 0..self.width {
             for y in 0..self.height {
-                self.map[x as usize][y as usize].draw(canvas);
+                if let Some(ObjectKind::Conveyor(dir)) = self.peek(x, y, &Layer::Floor).map(|object| object.kind()) {
+                    if let Some(object) = self.peek(x, y, &Layer::Solid) {
+                        if object.pushable() {
+                            riders.push(((x, y), dir, object.get_id()));
+                        }
+                    }
+                }
+            }
+        }
+        let mut frame = DeltaFrame::new();
+        let mut carried = HashSet::new();
+        for ((x, y), dir, id) in riders {
+            // Skip riders that were already shoved off their belt by an earlier one
+            if carried.contains(&id) || self.peek(x, y, &Layer::Solid).map(|object| object.get_id()) != Some(id) {
+                continue;
+            }
+            // Conveyors are strong enough to move anything that isn't stuck
+            if let Some(moved) = self.push_from((x, y), dir, None, &mut frame) {
+                carried.extend(moved);
+            }
+        }
+        if !frame.trivial() {
+            delta.push(Box::new(ConveyorDelta {
+                frame,
+            }));
+        }
+    }
+    
+    fn get_player_facing(&self) -> (i32, i32) {
+        unsafe {
+            (*self.player).facing
+        }
+    }
+    
+    fn set_facing(&mut self, dir: (i32, i32)) {
+        unsafe {
+            (*self.player).facing = dir;
+        }
+    }
+    
+    fn face(&mut self, dir: (i32, i32), delta: &mut DeltaFrame) {
+        let facing = self.get_player_facing();
+        if facing != dir {
+            delta.push(Box::new(FacingDelta {
+                facing,
+            }));
+            self.set_facing(dir);
+        }
+    }
+    
+    // Open the door at (x, y) if the player has the right key for it
+    fn unlock(&mut self, (x, y): (i32, i32), delta: &mut DeltaFrame) -> bool {
+        let tint = match self.peek(x, y, &Layer::Solid).map(|object| object.kind()) {
+            Some(ObjectKind::Door(tint)) => tint,
+            _ => return false,
+        };
+        match self.inventory.iter().position(|&key| key == tint) {
+            Some(index) => {
+                self.inventory.remove(index);
+                delta.push(Box::new(InventoryDelta {
+                    tint,
+                    gained: false,
+                }));
+                self.delete(x, y, &Layer::Solid, delta)
+            },
+            None => false,
+        }
+    }
+    
+    fn pick_up_key(&mut self, delta: &mut DeltaFrame) {
+        let (x, y) = self.get_player_pos();
+        if let Some(ObjectKind::Key(tint)) = self.peek(x, y, &Layer::Floor).map(|object| object.kind()) {
+            self.delete(x, y, &Layer::Floor, delta);
+            self.inventory.push(tint);
+            delta.push(Box::new(InventoryDelta {
+                tint,
+                gained: true,
+            }));
+        }
+    }
+    
+    // Later, restrict the range based on the camera
+    // Each layer is drawn in full before the next, so big objects aren't covered by their neighbours' floors
+    fn draw(&self, canvas: &mut dyn Paint, context: &DrawContext) {
+        for index in 0..NUMBER_OF_LAYERS {
+            for x in 0..self.width {
+                for y in 0..self.height {
+                    self.map[x as usize][y as usize].draw_layer(index, canvas, context);
+                }
             }
         }
     }
@@ -537,11 +1763,54 @@ impl WorldMap {
     // Slightly repetitive code lets us avoid unwrapping the inner Option
     // Note that "None" can mean two very different things here!!
     fn view(&mut self, x: i32, y: i32, layer: &Layer) -> Option<&mut Box<dyn GameObject>> {
-        if self.invalid(x, y) {
-            None
-        } else {
-            self.map[x as usize][y as usize].view(layer)
+        let (x, y) = self.anchor(x, y, layer)?;
+        self.map[x as usize][y as usize].view(layer)
+    }
+    
+    // The object covering (x, y), which for a big object may live in a different cell
+    fn peek(&self, x: i32, y: i32, layer: &Layer) -> Option<&dyn GameObject> {
+        let (x, y) = self.anchor(x, y, layer)?;
+        self.map[x as usize][y as usize].peek(layer)
+    }
+    
+    // Which cell holds the object covering (x, y)
+    // A big object lives in its top left cell, so we look up and to the left of (x, y) for it.
+    fn anchor(&self, x: i32, y: i32, layer: &Layer) -> Option<(i32, i32)> {
+        for ax in (x - MAX_OBJECT_SIZE + 1..=x).rev() {
+            for ay in (y - MAX_OBJECT_SIZE + 1..=y).rev() {
+                if self.invalid(ax, ay) {
+                    continue;
+                }
+                if let Some(object) = self.map[ax as usize][ay as usize].peek(layer) {
+                    let (width, height) = object.size();
+                    if x < ax + width && y < ay + height {
+                        return Some((ax, ay));
+                    }
+                }
+            }
+        }
+        None
+    }
+    
+    // The level is solved when every goal has a block of its color on it (and there is at least one goal)
+    fn solved(&self) -> bool {
+        let mut goals = 0;
+        for x in 0..self.width {
+            for y in 0..self.height {
+                if let Some(floor) = self.peek(x, y, &Layer::Floor) {
+                    let tint = match floor.kind() {
+                        ObjectKind::Goal(tint) => tint,
+                        _ => continue,
+                    };
+                    goals += 1;
+                    match self.peek(x, y, &Layer::Solid) {
+                        Some(object) if object.kind() == ObjectKind::Block(tint) => {},
+                        _ => return false,
+                    }
+                }
+            }
         }
+        goals > 0
     }
     
     fn delete(&mut self, x: i32, y: i32, layer: &Layer, delta: &mut DeltaFrame) -> bool {
@@ -596,15 +1865,55 @@ impl WorldMap {
     }
 }
 
-fn main() {
+// The level we were asked to play, or the demo room if there isn't one
+fn load_world(level_path: &Option<String>) -> Result<WorldMap, String> {
+    match level_path {
+        Some(path) => level::load_level(path),
+        None => Ok(demo_map()),
+    }
+}
+
+// The small test room we use when no level file is given
+fn demo_map() -> WorldMap {
+    // NOTE: probably not the best way to initialize this...
+    let mut player = Box::new(Player::new(3,3));
+    let mut world_map = WorldMap::new(10,10, &mut (*player) as *mut Player);
+    world_map.put_quiet(player);
+    world_map.put_quiet(Box::new(Block::new_wall(5,5)));
+    world_map.put_quiet(Box::new(Block::new_block(8,4)));
+    world_map
+}
+
+// When the level file was last changed, if there is one
+fn modified_time(level_path: &Option<String>) -> Option<SystemTime> {
+    level_path.as_ref().and_then(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+}
+
+/// Open a window and play until it's closed
+fn play(mut world_map: WorldMap, mut level_path: Option<String>, vsync: bool, undo_walk_steps: bool) {
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
     let window = video_subsystem
         .window("Game", SCREEN_WIDTH, SCREEN_HEIGHT)
+        .resizable()
         .build()
         .unwrap();
         
-    let mut canvas = window.into_canvas().build().unwrap();
+    let mut canvas = if vsync {
+        window.into_canvas().present_vsync().build().unwrap()
+    } else {
+        window.into_canvas().build().unwrap()
+    };
+    
+    // The atlas is optional; without one everything is drawn as colored squares
+    let texture_creator = canvas.texture_creator();
+    let atlas = match TileAtlas::load(&texture_creator, sprite::ATLAS_PATH) {
+        Ok(atlas) => Some(atlas),
+        Err(e) => {
+            eprintln!("No tile atlas loaded from {} ({})", sprite::ATLAS_PATH, e);
+            None
+        },
+    };
     
     let mut key_movement = HashMap::new();
     {
@@ -619,6 +1928,36 @@ fn main() {
     let mut buffered_motion_key: Option<Keycode> = None;
     // Was it pressed since the last input was consumed
     let mut buffered_motion_fresh = false;
+    // Holding a key against a wall should only bump once
+    let mut bumped_key: Option<Keycode> = None;
+    let mut bump: Option<Bump> = None;
+    
+    // Moves still to be made by a click-to-walk, and whether the walk has taken its first step
+    let mut walk: VecDeque<(i32, i32)> = VecDeque::new();
+    let mut walk_started = false;
+    let mut clicked_cell: Option<(i32, i32)> = None;
+    // A block being dragged with the mouse, and where it was dropped
+    let mut drag_start: Option<(i32, i32)> = None;
+    let mut dropped_block: Option<((i32, i32), (i32, i32))> = None;
+    
+    // Short note shown to the player, and how many ticks it stays up for
+    let mut message: Option<(String, u32)> = None;
+    // F3 shows object ids, crowded cells and the last undo frame
+    let mut show_overlay = false;
+    let mut console = Console::new();
+    
+    // The level is reloaded whenever its file changes, so it can be edited while playing
+    let mut level_modified = modified_time(&level_path);
+    let mut last_reload_check = Instant::now();
+    
+    // Personal bests, and the name they're kept under for the current level
+    let mut progress = Progress::load();
+    let mut level_key = progress::level_key(&level_path);
+    progress.played(&level_key, progress::fingerprint(&world_map));
+    let mut level_start = Instant::now();
+    let mut level_select = LevelSelect::new();
+    // Only a move that solves the level counts, not loading one that starts out solved
+    let mut was_solved = world_map.solved();
     
     let mut prev_keys = HashSet::new();
     
@@ -628,102 +1967,479 @@ fn main() {
     
     let mut event_pump = sdl.event_pump().unwrap();
     
-    // NOTE: probably not the best way to initialize this...
-    let mut player = Box::new(Player::new(3,3));
-    let mut world_map = WorldMap::new(10,10, &mut (*player) as *mut Player);
-    world_map.put_quiet(player);
-    world_map.put_quiet(Box::new(Block::new_wall(5,5)));
-    world_map.put_quiet(Box::new(Block::new_block(8,4)));
+    let sounds = Sounds::new(&sdl);
+    
+    let mut undo_stack = UndoStack::new(MAX_UNDO_DEPTH);
+    
+    // Objects moved by the most recent move, for animating them into place
+    let mut animation: Vec<(usize, (i32, i32))> = vec!();
     
-    let mut undo_stack = UndoStack::new(1000);
+    let tick = Duration::new(0, 1_000_000_000u32 / TICKS_PER_SECOND);
+    // Real time that has passed but hasn't been simulated yet
+    let mut accumulator = Duration::new(0, 0);
+    let mut last_frame = Instant::now();
+    // Keys pressed since the last tick (there may be several frames per tick, or none)
+    let mut pending_keys: HashSet<Keycode> = HashSet::new();
+    
+    // Kept between frames so that mouse clicks can be mapped back to the cell under them
+    let mut draw_context = DrawContext::new(atlas.as_ref());
     
     'mainloop: loop {
-        canvas.set_draw_color(Color::RGB(150, 100, 150));
-        canvas.clear();
-        
-        let mut cur_delta_frame = DeltaFrame {
-            deltas: vec!(),
-        };
+        let now = Instant::now();
+        accumulator += (now - last_frame).min(MAX_FRAME_TIME);
+        last_frame = now;
         
         for event in event_pump.poll_iter() {
+            // While the console is open it gets all the typing
+            if console.open {
+                if let Event::Quit {..} = event {
+                    break 'mainloop
+                }
+                if let Some(line) = console.handle_event(&event) {
+                    console.execute(&line, &mut world_map, &mut undo_stack, &mut level_path, &progress);
+                    // Loading or saving from the console shouldn't look like an outside edit
+                    level_modified = modified_time(&level_path);
+                    // Neither should solutions from the console count as personal bests
+                    was_solved = world_map.solved();
+                    let key = progress::level_key(&level_path);
+                    if key != level_key {
+                        level_key = key;
+                        progress.played(&level_key, progress::fingerprint(&world_map));
+                        level_start = Instant::now();
+                    }
+                    walk.clear();
+                    animation.clear();
+                    bump = None;
+                }
+                continue;
+            }
+            if level_select.open {
+                if let Event::Quit {..} = event {
+                    break 'mainloop
+                }
+                if let Some(path) = level_select.handle_event(&event) {
+                    match level::load_level(&path) {
+                        Ok(map) => {
+                            world_map = map;
+                            undo_stack = UndoStack::new(MAX_UNDO_DEPTH);
+                            level_path = Some(path);
+                            level_modified = modified_time(&level_path);
+                            level_key = progress::level_key(&level_path);
+                            progress.played(&level_key, progress::fingerprint(&world_map));
+                            level_start = Instant::now();
+                            was_solved = world_map.solved();
+                            walk.clear();
+                            animation.clear();
+                            bump = None;
+                        },
+                        Err(e) => message = Some((e, MESSAGE_LENGTH)),
+                    }
+                }
+                continue;
+            }
             match event {
                 Event::Quit {..}|
                 Event::KeyDown {keycode: Some(Keycode::Escape), ..} => {
                     break 'mainloop
                 },
+                Event::KeyDown {keycode: Some(Keycode::Backquote), repeat: false, ..} => {
+                    console.open = true;
+                },
+                Event::KeyDown {keycode: Some(Keycode::F3), repeat: false, ..} => {
+                    show_overlay = !show_overlay;
+                },
+                Event::KeyDown {keycode: Some(Keycode::L), repeat: false, ..} => {
+                    level_select.show(&level_path, &progress);
+                },
+                Event::KeyDown {keycode: Some(Keycode::F11), repeat: false, ..} => {
+                    let window = canvas.window_mut();
+                    let fullscreen = match window.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off,
+                    };
+                    if let Err(e) = window.set_fullscreen(fullscreen) {
+                        eprintln!("Couldn't toggle fullscreen: {}", e);
+                    }
+                },
+                Event::MouseButtonDown {mouse_btn: MouseButton::Left, x, y, ..} => {
+                    let (cx, cy) = draw_context.cell_at((x, y));
+                    // Grabbing a block starts a drag, clicking anywhere else walks there
+                    match world_map.peek(cx, cy, &Layer::Solid) {
+                        Some(object) if object.pushable() && object.kind() != ObjectKind::Player => drag_start = Some((cx, cy)),
+                        _ => clicked_cell = Some((cx, cy)),
+                    }
+                },
+                Event::MouseButtonUp {mouse_btn: MouseButton::Left, x, y, ..} => {
+                    if let Some(from) = drag_start.take() {
+                        dropped_block = Some((from, draw_context.cell_at((x, y))));
+                    }
+                },
                 _ => (),
             }
         }
-        // Get key presses, releases, and holds
-        let keys: HashSet<Keycode> = event_pump.keyboard_state()
-            .pressed_scancodes().filter_map(Keycode::from_scancode).collect();
-        let new_keys = &keys - &prev_keys;
+        // Get key presses, releases, and holds (unless they're typing into the console or picking a level)
+        let keys: HashSet<Keycode> = if console.open || level_select.open {
+            HashSet::new()
+        } else {
+            event_pump.keyboard_state().pressed_scancodes().filter_map(Keycode::from_scancode).collect()
+        };
+        pending_keys.extend(&keys - &prev_keys);
+        prev_keys = keys;
         
-        for key in new_keys.iter() {
-            if key_movement.contains_key(key) {
-                buffered_motion_key = Some(*key);
-                buffered_motion_fresh = true;
+        if now - last_reload_check >= RELOAD_CHECK_INTERVAL {
+            last_reload_check = now;
+            let modified = modified_time(&level_path);
+            if modified.is_some() && modified != level_modified {
+                level_modified = modified;
+                // A half-saved file might not parse; keep playing the old version until the next save
+                match load_world(&level_path) {
+                    Ok(map) => {
+                        world_map = map;
+                        undo_stack = UndoStack::new(MAX_UNDO_DEPTH);
+                        walk.clear();
+                        animation.clear();
+                        bump = None;
+                        // Bests for the old version don't count for the edited one
+                        progress.played(&level_key, progress::fingerprint(&world_map));
+                        level_start = Instant::now();
+                        was_solved = world_map.solved();
+                        message = Some(("Level reloaded".to_string(), MESSAGE_LENGTH));
+                    },
+                    Err(e) => eprintln!("Couldn't reload: {}", e),
+                }
             }
         }
         
-        match anim_state {
-            AnimationState::Ready => {
-                // If the buffered key is stale and no longer held, find a new one
-                if !buffered_motion_fresh && (
-                    buffered_motion_key == None ||
-                    !keys.contains(&buffered_motion_key.unwrap())
-                ) {
-                    buffered_motion_key = None;
-                    for (key, _) in key_movement.iter() {
-                        if keys.contains(key) {
-                            buffered_motion_key = Some(*key);
-                        }
+        while accumulator >= tick {
+            accumulator -= tick;
+            let keys = &prev_keys;
+            let new_keys = mem::take(&mut pending_keys);
+            
+            let mut cur_delta_frame = DeltaFrame {
+                deltas: vec!(),
+            };
+            let solved_before_tick = world_map.solved();
+            
+            for key in new_keys.iter() {
+                if key_movement.contains_key(key) {
+                    buffered_motion_key = Some(*key);
+                    buffered_motion_fresh = true;
+                    // Taking control with the keyboard cancels a walk
+                    walk.clear();
+                }
+            }
+            
+            if let Some((x, y)) = clicked_cell.take() {
+                if !world_map.invalid(x, y) && world_map.peek(x, y, &Layer::Solid).is_none() {
+                    match path::walk_path(&world_map, (x, y)) {
+                        Some(steps) => {
+                            walk = steps.into_iter().collect();
+                            walk_started = false;
+                            buffered_motion_key = None;
+                            buffered_motion_fresh = false;
+                        },
+                        None => sounds.play(SoundEvent::Blocked),
                     }
                 }
-                match buffered_motion_key {
-                    Some(key) => {
-                        if world_map.move_solid(*key_movement.get(&key).unwrap(), &mut cur_delta_frame) {
-                            anim_state = AnimationState::Wait(ANIMATION_LENGTH);
-                            // The keypress has been consumed, and is no longer fresh
-                            undo_cooldown = 0;
+            }
+            
+            if let Some((from, to)) = dropped_block.take() {
+                if from != to {
+                    match path::push_path(&world_map, from, to) {
+                        Some(steps) => {
+                            walk = steps.into_iter().collect();
+                            walk_started = false;
+                            buffered_motion_key = None;
                             buffered_motion_fresh = false;
+                        },
+                        None => {
+                            sounds.play(SoundEvent::Blocked);
+                            message = Some(("Can't push that block there".to_string(), MESSAGE_LENGTH));
+                        },
+                    }
+                }
+            }
+            
+            if let Some((_, ref mut ticks_left)) = message {
+                *ticks_left = ticks_left.saturating_sub(1);
+            }
+            if message.as_ref().is_some_and(|&(_, ticks_left)| ticks_left == 0) {
+                message = None;
+            }
+            
+            // Whether this tick's frame is a step of a walk
+            let mut walked = false;
+            match anim_state {
+                // Once caught, the only way out is undoing or restarting
+                AnimationState::Ready if world_map.caught() => {
+                    walk.clear();
+                    buffered_motion_key = None;
+                },
+                AnimationState::Ready if !walk.is_empty() => {
+                    let dir = walk.pop_front().unwrap();
+                    let (x, y) = world_map.get_player_pos();
+                    if world_map.take_turn(dir, &mut cur_delta_frame) {
+                        anim_state = AnimationState::Wait(ANIMATION_LENGTH);
+                        undo_cooldown = 0;
+                        bump = None;
+                        walked = true;
+                        // Stop if something carried or shoved the player off the planned path
+                        if world_map.get_player_pos() != (x + dir.0, y + dir.1) {
+                            walk.clear();
                         }
-                    },
-                    None => {},
+                    } else {
+                        // Something got in the way since we planned the walk
+                        walk.clear();
+                    }
+                },
+                AnimationState::Ready => {
+                    // If the buffered key is stale and no longer held, find a new one
+                    if !buffered_motion_fresh && (
+                        buffered_motion_key == None ||
+                        !keys.contains(&buffered_motion_key.unwrap())
+                    ) {
+                        buffered_motion_key = None;
+                        for (key, _) in key_movement.iter() {
+                            if keys.contains(key) {
+                                buffered_motion_key = Some(*key);
+                            }
+                        }
+                    }
+                    match buffered_motion_key {
+                        Some(key) => {
+                            let dir = *key_movement.get(&key).unwrap();
+                            if world_map.take_turn(dir, &mut cur_delta_frame) {
+                                anim_state = AnimationState::Wait(ANIMATION_LENGTH);
+                                // The keypress has been consumed, and is no longer fresh
+                                undo_cooldown = 0;
+                                buffered_motion_fresh = false;
+                                bumped_key = None;
+                                bump = None;
+                            } else if buffered_motion_fresh || bumped_key != Some(key) {
+                                let (stuck, _) = world_map.solid_chain(world_map.get_player_pos(), dir);
+                                bump = Some(Bump {
+                                    ids: stuck.values().cloned().collect(),
+                                    dir,
+                                    ticks_left: BUMP_LENGTH,
+                                });
+                                sounds.play(SoundEvent::Blocked);
+                                // A bump consumes the keypress just like a move would
+                                buffered_motion_fresh = false;
+                                bumped_key = Some(key);
+                            }
+                        },
+                        None => {},
+                    }
+                },
+                AnimationState::Wait(n) => {
+                    anim_state = if n > 0 {
+                        AnimationState::Wait(n-1)
+                    } else {
+                        AnimationState::Ready
+                    };
+                },
+            }
+            
+            if !cur_delta_frame.trivial() {
+                animation = cur_delta_frame.motions();
+                if !solved_before_tick && world_map.solved() {
+                    sounds.play(SoundEvent::LevelComplete);
+                } else if let Some(sound) = sound::move_sound(&cur_delta_frame, world_map.get_player_id()) {
+                    sounds.play(sound);
                 }
-            },
-            AnimationState::Wait(n) => {
-                anim_state = if n > 0 {
-                    AnimationState::Wait(n-1)
+                if walked && walk_started && !undo_walk_steps {
+                    undo_stack.push_joined(cur_delta_frame);
                 } else {
-                    AnimationState::Ready
-                };
-            },
+                    undo_stack.push(cur_delta_frame);
+                }
+                walk_started = walked;
+            }
+            
+            if keys.contains(&Keycode::Z) {
+                walk.clear();
+            }
+            if new_keys.contains(&Keycode::Z) {
+                if undo_stack.pop(&mut world_map) {
+                    sounds.play(SoundEvent::Undo);
+                }
+                undo_cooldown = UNDO_COOLDOWN_MAX;
+                animation.clear();
+            } else if keys.contains(&Keycode::Z) {
+                if undo_cooldown == 0 {
+                    if undo_stack.pop(&mut world_map) {
+                        sounds.play(SoundEvent::Undo);
+                    }
+                    undo_cooldown = UNDO_COOLDOWN_MAX;
+                    animation.clear();
+                }
+            }
+            
+            if undo_cooldown > 0 {
+                undo_cooldown -= 1;
+            }
+            
+            if let Some(ref mut b) = bump {
+                b.ticks_left = b.ticks_left.saturating_sub(1);
+            }
+            if bump.as_ref().is_some_and(|b| b.ticks_left == 0) {
+                bump = None;
+            }
+            
+            // R starts the level over from scratch
+            if new_keys.contains(&Keycode::R) {
+                match load_world(&level_path) {
+                    Ok(map) => {
+                        world_map = map;
+                        undo_stack = UndoStack::new(MAX_UNDO_DEPTH);
+                        walk.clear();
+                        animation.clear();
+                        bump = None;
+                        level_start = Instant::now();
+                    },
+                    Err(e) => eprintln!("Couldn't restart: {}", e),
+                }
+            }
+            
+            // C copies the moves so far as LURD, V plays a LURD solution from the clipboard
+            if new_keys.contains(&Keycode::C) {
+                let solution = lurd::export(&undo_stack, world_map.get_player_id());
+                println!("{}", solution);
+                if let Err(e) = video_subsystem.clipboard().set_clipboard_text(&solution) {
+                    eprintln!("Couldn't copy solution: {}", e);
+                }
+            } else if new_keys.contains(&Keycode::V) {
+                match video_subsystem.clipboard().clipboard_text() {
+                    Ok(text) => if let Err(e) = lurd::import(&mut world_map, &mut undo_stack, &text) {
+                        eprintln!("{}", e);
+                    },
+                    Err(e) => eprintln!("Couldn't paste solution: {}", e),
+                }
+                animation.clear();
+            }
         }
         
-        if !cur_delta_frame.trivial() {
-            undo_stack.push(cur_delta_frame);
+        // Solving the level may have set a new personal best
+        let solved = world_map.solved();
+        if solved && !was_solved {
+            let solution = lurd::export(&undo_stack, world_map.get_player_id());
+            let pushes = solution.chars().filter(|c| c.is_ascii_uppercase()).count();
+            if progress.solved(&level_key, solution.len(), pushes, level_start.elapsed()) {
+                message = Some(("New personal best!".to_string(), MESSAGE_LENGTH));
+            }
         }
+        was_solved = solved;
         
-        if new_keys.contains(&Keycode::Z) {
-            undo_stack.pop(&mut world_map);
-            undo_cooldown = UNDO_COOLDOWN_MAX;
-        } else if keys.contains(&Keycode::Z) {
-            if undo_cooldown == 0 {
-                undo_stack.pop(&mut world_map);
-                undo_cooldown = UNDO_COOLDOWN_MAX;
+        // How far we are between the last tick and the next one
+        let alpha = accumulator.as_secs_f32() / tick.as_secs_f32();
+        draw_context.offsets.clear();
+        // The window may have been resized, so fit the level to it every frame
+        let (screen_width, screen_height) = canvas.output_size().unwrap();
+        let map_size = (world_map.width, world_map.height);
+        draw_context.fit(map_size, Rect::new(0, 0, screen_width, screen_height.saturating_sub(HUD_HEIGHT).max(1)));
+        if let AnimationState::Wait(n) = anim_state {
+            // Slide moved objects from where they were, by the fraction of the animation left
+            let left = ((n as f32 - alpha) / ANIMATION_LENGTH as f32).max(0.0);
+            // An object can move more than once in a turn (pushed, then carried by a conveyor)
+            for &(id, (dx, dy)) in animation.iter() {
+                let offset = draw_context.offsets.entry(id).or_insert((0.0, 0.0));
+                offset.0 -= dx as f32 * left;
+                offset.1 -= dy as f32 * left;
+            }
+        }
+        if let Some(ref b) = bump {
+            // Out toward the obstacle and back again
+            let t = ((BUMP_LENGTH - b.ticks_left) as f32 + alpha) / BUMP_LENGTH as f32;
+            let distance = BUMP_DISTANCE * (t.min(1.0) * std::f32::consts::PI).sin();
+            for id in b.ids.iter() {
+                draw_context.offsets.insert(*id, (b.dir.0 as f32 * distance, b.dir.1 as f32 * distance));
             }
         }
         
-        if undo_cooldown > 0 {
-            undo_cooldown -= 1;
+        canvas.set_draw_color(LETTERBOX_COLOR);
+        canvas.clear();
+        canvas.set_draw_color(BACKGROUND_COLOR);
+        canvas.fill_rect(draw_context.map_rect(map_size)).expect("Failed to draw map background");
+        
+        world_map.draw(&mut canvas, &draw_context);
+        
+        // Outline where a dragged block would be dropped
+        if drag_start.is_some() {
+            let mouse = event_pump.mouse_state();
+            let (x, y) = draw_context.cell_at((mouse.x(), mouse.y()));
+            canvas.set_draw_color(TEXT_COLOR);
+            canvas.draw_rect(draw_context.cell_rect((x, y))).expect("Failed to draw drop target");
         }
         
-        world_map.draw(&mut canvas);
+        if show_overlay {
+            overlay::draw(&mut canvas, &world_map, &draw_context, &undo_stack);
+        }
         
-        prev_keys = keys;
+        let mut hud = format!("Moves: {}", lurd::move_count(&undo_stack, world_map.get_player_id()));
+        if !world_map.inventory.is_empty() {
+            let keys: Vec<String> = world_map.inventory.iter().map(|tint| format!("{:?}", tint)).collect();
+            hud += &format!("  Keys: {}", keys.join(" "));
+        }
+        if world_map.solved() {
+            hud += "  Solved!";
+        }
+        if world_map.caught() {
+            hud += "  Caught! Z to undo, R to restart";
+        }
+        if let Some(record) = progress.get(&level_key).filter(|record| record.solved) {
+            hud += &format!("  Best: {}", record.describe());
+        }
+        let (_, hud_height) = font::text_size(&hud, 2);
+        canvas.draw_text(&hud, (8, screen_height as i32 - hud_height as i32 - 8), 2, TEXT_COLOR);
+        if let Some((ref text, _)) = message {
+            canvas.draw_text(text, (8, 8), 2, TEXT_COLOR);
+        }
+        if console.open {
+            console.draw(&mut canvas);
+        }
+        if level_select.open {
+            level_select.draw(&mut canvas, &progress);
+        }
         
         canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        // With vsync, present() already waited for us; otherwise sleep until the next tick is due
+        if !vsync && accumulator < tick {
+            ::std::thread::sleep(tick - accumulator);
+        }
+    }
+}
+
+fn main() {
+    std::process::exit(cli::run(std::env::args().skip(1).collect()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_rebuilds_a_wall_and_its_bomb() {
+        let text = "#######\n#@!%  #\n#######\n";
+        let mut map = level::parse_level(text).unwrap();
+        let mut undo_stack = UndoStack::new(MAX_UNDO_DEPTH);
+        let mut frame = DeltaFrame::new();
+        assert!(map.take_turn((1, 0), &mut frame));
+        assert_eq!(level::write_level(&map).unwrap(), "#######\n# @   #\n#######\n");
+        undo_stack.push(frame);
+        assert!(undo_stack.pop(&mut map));
+        assert_eq!(level::write_level(&map).unwrap(), text);
+    }
+
+    #[test]
+    fn bomb_stays_put_when_the_push_is_still_blocked() {
+        // The bomb clears the bottom half of the big block's way, but the plain wall still stops the top half
+        let text = "Strength: unlimited\n########\n#      #\n#@  #  #\n#   !% #\n########\n[big blocks]\n3 3 2 2\n";
+        let mut map = level::parse_level(text).unwrap();
+        map.inventory.push(Tint::Red);
+        let before = level::write_level(&map).unwrap();
+        let mut frame = DeltaFrame::new();
+        assert!(!map.take_turn((1, 0), &mut frame));
+        assert!(frame.trivial());
+        assert_eq!(level::write_level(&map).unwrap(), before);
+        assert!(map.inventory == vec!(Tint::Red));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,67 @@
+use {DrawContext, UndoStack, WorldMap, NUMBER_OF_LAYERS};
+use font::{self, DrawText};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+const LABEL_COLOR: Color = Color { r: 255, g: 255, b: 255, a: 255 };
+const CROWDED_COLOR: Color = Color { r: 255, g: 40, b: 40, a: 255 };
+const PANEL_COLOR: Color = Color { r: 0, g: 0, b: 0, a: 190 };
+const LINE_HEIGHT: i32 = font::GLYPH_HEIGHT as i32 + 2;
+
+// Short names, so labels fit inside a tile
+fn layer_letter(index: usize) -> char {
+    match index {
+        0 => 'F',
+        1 => 'P',
+        _ => 'S',
+    }
+}
+
+/// Label every object with its id and layer, outline cells where a layer holds more than one object,
+/// and list the undo stack depth and the changes in the most recent frame
+pub fn draw(canvas: &mut WindowCanvas, map: &WorldMap, context: &DrawContext, undo_stack: &UndoStack) {
+    for x in 0..map.width {
+        for y in 0..map.height {
+            let cell = &map.map[x as usize][y as usize];
+            let rect = context.cell_rect((x, y));
+            let mut line = 0;
+            for index in 0..NUMBER_OF_LAYERS {
+                let objects = &cell.layers[index];
+                for object in objects.iter() {
+                    let label = format!("{}{}", object.get_id(), layer_letter(index));
+                    canvas.draw_text(&label, (rect.x() + 2, rect.y() + 2 + line * LINE_HEIGHT), 1, LABEL_COLOR);
+                    line += 1;
+                }
+                // Most of the game logic assumes this never happens
+                if objects.len() > 1 {
+                    canvas.set_draw_color(CROWDED_COLOR);
+                    canvas.draw_rect(rect).expect("Failed to outline crowded cell");
+                }
+            }
+        }
+    }
+
+    let mut lines = vec!(format!("Undo depth: {}", undo_stack.size));
+    match undo_stack.last() {
+        Some(frame) => {
+            lines.push(format!("Last frame ({} changes):", frame.deltas.len()));
+            lines.extend(frame.deltas.iter().map(|delta| format!("  {}", delta.describe())));
+        },
+        None => lines.push("Nothing to undo".to_string()),
+    }
+    lines.push(format!("Player #{} at {:?}", map.get_player_id(), map.get_player_pos()));
+
+    let width = lines.iter().map(|line| font::text_size(line, 1).0).max().unwrap_or(0) + 8;
+    let height = (lines.len() as i32 * LINE_HEIGHT + 6) as u32;
+    let (screen_width, _) = canvas.output_size().expect("Failed to get screen size");
+    let left = screen_width as i32 - width as i32 - 4;
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(PANEL_COLOR);
+    canvas.fill_rect(Rect::new(left, 4, width, height)).expect("Failed to draw overlay panel");
+    canvas.set_blend_mode(BlendMode::None);
+    for (i, line) in lines.iter().enumerate() {
+        canvas.draw_text(line, (left + 4, 8 + i as i32 * LINE_HEIGHT), 1, LABEL_COLOR);
+    }
+}
@@ -0,0 +1,159 @@
+use {Layer, ObjectKind, WorldMap, DIRECTIONS};
+
+use std::collections::{HashMap, VecDeque};
+
+// (block position, player position), where a big block's position is its top left cell
+type PushState = ((i32, i32), (i32, i32));
+
+/// Shortest walk from start to target over cells that are open, as a list of directions
+/// The start cell itself doesn't need to be open (the player is usually standing on it).
+fn search<F>(start: (i32, i32), target: (i32, i32), open: F) -> Option<Vec<(i32, i32)>>
+    where F: Fn((i32, i32)) -> bool
+{
+    if start == target {
+        return Some(vec!());
+    }
+    // Each visited cell, and the direction we stepped in to reach it
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some((x, y)) = queue.pop_front() {
+        for &(dx, dy) in DIRECTIONS.iter() {
+            let next = (x + dx, y + dy);
+            if next == start || came_from.contains_key(&next) || !open(next) {
+                continue;
+            }
+            came_from.insert(next, (dx, dy));
+            if next == target {
+                let mut path = vec!();
+                let mut pos = target;
+                while pos != start {
+                    let (dx, dy) = came_from[&pos];
+                    path.push((dx, dy));
+                    pos = (pos.0 - dx, pos.1 - dy);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            queue.push_back(next);
+        }
+    }
+    None
+}
+
+// A belt would carry the player (or a block) off the planned path, maybe into something else
+fn conveyor(map: &WorldMap, (x, y): (i32, i32)) -> bool {
+    map.peek(x, y, &Layer::Floor).is_some_and(|object| matches!(object.kind(), ObjectKind::Conveyor(_)))
+}
+
+/// Shortest way for the player to walk to target without pushing anything
+pub fn walk_path(map: &WorldMap, target: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+    search(map.get_player_pos(), target, |(x, y)| {
+        !map.invalid(x, y) && map.peek(x, y, &Layer::Solid).is_none() && !conveyor(map, (x, y))
+    })
+}
+
+/// Which way to step from `from` to get closer to `to`, going around solid objects
+pub fn chase_step(map: &WorldMap, from: (i32, i32), to: (i32, i32)) -> Option<(i32, i32)> {
+    let path = search(from, to, |(x, y)| {
+        !map.invalid(x, y) && (map.peek(x, y, &Layer::Solid).is_none() || (x, y) == to)
+    })?;
+    path.first().cloned()
+}
+
+/// Walks and pushes that get the block at `block` to `target` without disturbing anything else,
+/// as a list of player moves. This only ever pushes that one block, one cell at a time.
+/// For a big block, the part at `block` is the part that ends up at `target`.
+pub fn push_path(map: &WorldMap, block: (i32, i32), target: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+    let player = map.get_player_pos();
+    let (corner, (width, height)) = {
+        let object = map.peek(block.0, block.1, &Layer::Solid)?;
+        (object.get_pos(), object.size())
+    };
+    let target = (target.0 - (block.0 - corner.0), target.1 - (block.1 - corner.1));
+    let covers = |(bx, by): (i32, i32), (x, y): (i32, i32)| {
+        x >= bx && x < bx + width && y >= by && y < by + height
+    };
+    // Everything but the player and the block we're moving stays where it is
+    let free = |(x, y): (i32, i32)| {
+        !map.invalid(x, y) && !conveyor(map, (x, y))
+            && (map.peek(x, y, &Layer::Solid).is_none() || (x, y) == player || covers(corner, (x, y)))
+    };
+    let fits = |(bx, by): (i32, i32)| {
+        (0..width).all(|dx| (0..height).all(|dy| free((bx + dx, by + dy))))
+    };
+    if !fits(target) {
+        return None;
+    }
+    // Apart from the start, we only keep the states right after a push
+    let start = (corner, player);
+    let mut came_from: HashMap<PushState, (PushState, Vec<(i32, i32)>)> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(state) = queue.pop_front() {
+        let (b, p) = state;
+        if b == target {
+            let mut moves = vec!();
+            let mut state = state;
+            while state != start {
+                let (ref prev, ref steps) = came_from[&state];
+                moves.splice(0..0, steps.iter().cloned());
+                state = *prev;
+            }
+            return Some(moves);
+        }
+        for &(dx, dy) in DIRECTIONS.iter() {
+            let dest = (b.0 + dx, b.1 + dy);
+            if !fits(dest) {
+                continue;
+            }
+            // Any cell behind the block's trailing edge will do; take the closest
+            let mut best: Option<Vec<(i32, i32)>> = None;
+            let mut pushed_from = b;
+            for x in b.0..b.0 + width {
+                for y in b.1..b.1 + height {
+                    let stand = (x - dx, y - dy);
+                    if covers(b, stand) {
+                        continue;
+                    }
+                    if let Some(steps) = search(p, stand, |cell| !covers(b, cell) && free(cell)) {
+                        if best.as_ref().is_none_or(|best| steps.len() < best.len()) {
+                            best = Some(steps);
+                            pushed_from = (x, y);
+                        }
+                    }
+                }
+            }
+            if let Some(mut steps) = best {
+                // The player ends up in the cell the block was pushed from
+                let next = (dest, pushed_from);
+                if next == start || came_from.contains_key(&next) {
+                    continue;
+                }
+                steps.push((dx, dy));
+                came_from.insert(next, (state, steps));
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use level;
+
+    #[test]
+    fn walks_go_around_conveyors() {
+        // Stepping onto the belt would carry the player down into the block
+        let map = level::parse_level("########\n#@ v   #\n#  $   #\n#  .   #\n#      #\n########\n").unwrap();
+        let path = walk_path(&map, (5, 1)).unwrap();
+        let mut pos = map.get_player_pos();
+        for &(dx, dy) in path.iter() {
+            pos = (pos.0 + dx, pos.1 + dy);
+            assert_ne!(pos, (3, 1), "walked over the conveyor");
+        }
+        assert_eq!(pos, (5, 1));
+    }
+}
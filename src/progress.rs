@@ -0,0 +1,259 @@
+//! The player's personal bests for each level, kept in a save file in their data directory.
+//! The file is plain text, one level per line:
+//!   solved best_moves best_pushes best_time_ms fingerprint level
+//! with "-" for anything not set yet. The level comes last so it can hold spaces.
+
+use WorldMap;
+use level;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SAVE_FILE: &str = "progress.txt";
+// SDL picks the data directory (e.g. ~/.local/share/sokoban-prototype) from these
+const ORG_NAME: &str = "";
+const APP_NAME: &str = "sokoban-prototype";
+// The built-in room that's played when no level is given
+const DEMO_LEVEL: &str = "demo";
+
+/// Everything remembered about one level
+#[derive(Clone, Copy, Default)]
+pub struct Record {
+    pub solved: bool,
+    // Each best is kept on its own, so they can come from different solutions
+    pub best_moves: Option<usize>,
+    pub best_pushes: Option<usize>,
+    pub best_time: Option<Duration>,
+    // Which version of the level the bests are for
+    pub fingerprint: u64,
+}
+
+impl Record {
+    /// Short summary for the HUD and console
+    pub fn describe(&self) -> String {
+        if !self.solved {
+            return "not solved yet".to_string();
+        }
+        let mut parts = vec!();
+        if let Some(moves) = self.best_moves {
+            parts.push(format!("{} moves", moves));
+        }
+        if let Some(pushes) = self.best_pushes {
+            parts.push(format!("{} pushes", pushes));
+        }
+        if let Some(time) = self.best_time {
+            parts.push(format_time(time));
+        }
+        parts.join(", ")
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Identifies one version of a level, so bests don't carry over once it's been edited
+/// Only the level's contents count, not comments or spacing in its file.
+pub fn fingerprint(map: &WorldMap) -> u64 {
+    let text = level::write_level(map).unwrap_or_default();
+    // FNV-1a, which unlike the standard library's hasher gives the same answer in every build
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Names the level a record belongs to
+/// The same file reached by different relative paths should share a record.
+pub fn level_key(level_path: &Option<String>) -> String {
+    match level_path {
+        Some(path) => fs::canonicalize(path).map_or_else(|_| path.clone(), |path| path.display().to_string()),
+        None => DEMO_LEVEL.to_string(),
+    }
+}
+
+pub struct Progress {
+    // None if there's nowhere to save, in which case progress only lasts until the game closes
+    path: Option<PathBuf>,
+    records: BTreeMap<String, Record>,
+}
+
+impl Progress {
+    /// Load the save file from the player's data directory; no file just means no progress yet
+    pub fn load() -> Progress {
+        match sdl2::filesystem::pref_path(ORG_NAME, APP_NAME) {
+            Ok(dir) => Progress::load_from(Path::new(&dir).join(SAVE_FILE)),
+            Err(e) => {
+                eprintln!("Progress won't be saved: {}", e);
+                Progress {
+                    path: None,
+                    records: BTreeMap::new(),
+                }
+            },
+        }
+    }
+
+    fn load_from(path: PathBuf) -> Progress {
+        let mut records = BTreeMap::new();
+        if let Ok(text) = fs::read_to_string(&path) {
+            for line in text.lines() {
+                match parse_line(line) {
+                    Some((level, record)) => {
+                        records.insert(level, record);
+                    },
+                    None => eprintln!("Skipping unreadable line in {}: {:?}", path.display(), line),
+                }
+            }
+        }
+        Progress {
+            path: Some(path),
+            records,
+        }
+    }
+
+    pub fn get(&self, level: &str) -> Option<&Record> {
+        self.records.get(level)
+    }
+
+    /// Every level played so far, in order of name
+    pub fn records(&self) -> impl Iterator<Item = (&String, &Record)> {
+        self.records.iter()
+    }
+
+    /// Note that a level has been started, so it shows up even before it's solved
+    /// If the level has changed since its bests were set, they're thrown away.
+    pub fn played(&mut self, level: &str, fingerprint: u64) {
+        if self.records.get(level).is_none_or(|record| record.fingerprint != fingerprint) {
+            self.records.insert(level.to_string(), Record {
+                fingerprint,
+                ..Record::default()
+            });
+            self.save();
+        }
+    }
+
+    /// Record a solution, and return whether it beat any of the old bests
+    pub fn solved(&mut self, level: &str, moves: usize, pushes: usize, time: Duration) -> bool {
+        let record = self.records.entry(level.to_string()).or_default();
+        let improved = !record.solved
+            || record.best_moves.is_none_or(|best| moves < best)
+            || record.best_pushes.is_none_or(|best| pushes < best)
+            || record.best_time.is_none_or(|best| time < best);
+        record.solved = true;
+        record.best_moves = Some(record.best_moves.map_or(moves, |best| best.min(moves)));
+        record.best_pushes = Some(record.best_pushes.map_or(pushes, |best| best.min(pushes)));
+        record.best_time = Some(record.best_time.map_or(time, |best| best.min(time)));
+        if improved {
+            self.save();
+        }
+        improved
+    }
+
+    // Failing to save shouldn't stop the game, so this just complains
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = self.write(path) {
+                eprintln!("Couldn't save progress to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    // Write a new file next to the old one, then swap it in, so a crash
+    // part way through leaves the old progress instead of half of the new
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        let temp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&temp_path)?;
+        for (level, record) in self.records.iter() {
+            writeln!(file, "{}", format_line(level, record))?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    }
+}
+
+fn format_line(level: &str, record: &Record) -> String {
+    fn field<T: ToString>(value: Option<T>) -> String {
+        value.map_or("-".to_string(), |value| value.to_string())
+    }
+    format!("{} {} {} {} {:016x} {}", record.solved, field(record.best_moves), field(record.best_pushes),
+        field(record.best_time.map(|time| time.as_millis())), record.fingerprint, level)
+}
+
+fn parse_line(line: &str) -> Option<(String, Record)> {
+    fn field<T: std::str::FromStr>(text: &str) -> Option<Option<T>> {
+        match text {
+            "-" => Some(None),
+            _ => text.parse().ok().map(Some),
+        }
+    }
+    let mut parts = line.splitn(6, ' ');
+    let solved = parts.next()?.parse().ok()?;
+    let best_moves = field(parts.next()?)?;
+    let best_pushes = field(parts.next()?)?;
+    let best_time = field::<u64>(parts.next()?)?.map(Duration::from_millis);
+    let fingerprint = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let level = parts.next()?.to_string();
+    Some((level, Record {
+        solved,
+        best_moves,
+        best_pushes,
+        best_time,
+        fingerprint,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsaved() -> Progress {
+        Progress {
+            path: None,
+            records: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let record = Record {
+            solved: true,
+            best_moves: Some(42),
+            best_pushes: Some(7),
+            best_time: Some(Duration::from_millis(61_500)),
+            fingerprint: 0xdead_beef,
+        };
+        let line = format_line("/levels/with space.txt", &record);
+        let (level, parsed) = parse_line(&line).unwrap();
+        assert_eq!(level, "/levels/with space.txt");
+        assert_eq!(format_line(&level, &parsed), line);
+
+        let (_, empty) = parse_line(&format_line("demo", &Record::default())).unwrap();
+        assert!(!empty.solved && empty.best_moves.is_none() && empty.best_time.is_none());
+        assert!(parse_line("true 1 2").is_none());
+    }
+
+    #[test]
+    fn bests_are_kept_separately() {
+        let mut progress = unsaved();
+        progress.played("a", 1);
+        assert!(progress.solved("a", 10, 5, Duration::from_secs(30)));
+        assert!(!progress.solved("a", 12, 6, Duration::from_secs(40)));
+        assert!(progress.solved("a", 11, 4, Duration::from_secs(50)));
+        let record = progress.get("a").unwrap();
+        assert_eq!((record.best_moves, record.best_pushes), (Some(10), Some(4)));
+        assert_eq!(record.best_time, Some(Duration::from_secs(30)));
+        assert_eq!(record.describe(), "10 moves, 4 pushes, 0:30");
+    }
+
+    #[test]
+    fn editing_a_level_resets_its_bests() {
+        let mut progress = unsaved();
+        progress.played("a", 1);
+        progress.solved("a", 10, 5, Duration::from_secs(30));
+        progress.played("a", 1);
+        assert!(progress.get("a").unwrap().solved);
+        progress.played("a", 2);
+        assert!(!progress.get("a").unwrap().solved);
+    }
+}
@@ -0,0 +1,163 @@
+//! Breadth-first search for the shortest solution to a level, by actually playing moves
+//! on the map and undoing them. This knows nothing about the rules itself, so it handles
+//! every object the game does, but it's only practical for small levels.
+
+use {DeltaFrame, Layer, ObjectKind, Tint, WorldMap, DIRECTIONS};
+
+use std::collections::{HashSet, VecDeque};
+
+/// Give up after looking at this many distinct positions
+pub const DEFAULT_MAX_STATES: usize = 200_000;
+
+// Everything about a position that can affect what happens next
+// Objects are listed by their top left cell, so their size has to be kept too, or a big block
+// then a small one would look the same as a small block then a big one
+type State = (Vec<((i32, i32), ObjectKind, (i32, i32))>, Vec<Tint>);
+
+fn state(map: &WorldMap) -> State {
+    let mut objects = vec!();
+    for x in 0..map.width {
+        for y in 0..map.height {
+            for layer in [Layer::Floor, Layer::Player, Layer::Solid].iter() {
+                if let Some(object) = map.map[x as usize][y as usize].peek(layer) {
+                    objects.push(((x, y), object.kind(), object.size()));
+                }
+            }
+        }
+    }
+    let mut inventory = map.inventory.clone();
+    inventory.sort_by_key(|tint| *tint as usize);
+    (objects, inventory)
+}
+
+/// What came of a search
+pub enum Outcome {
+    Solved(Vec<(i32, i32)>),
+    // Every reachable position was tried
+    Unsolvable,
+    // Ran out of states to look at first
+    GaveUp,
+}
+
+pub struct Search {
+    pub outcome: Outcome,
+    pub states: usize,
+}
+
+/// Shortest list of moves that solves the level from where it is now
+/// The map is back where it started when this returns.
+pub fn solve(map: &mut WorldMap, max_states: usize) -> Search {
+    // Blocked moves turn the player without leaving anything to undo, so facing is put back by hand
+    let facing = map.get_player_facing();
+    let result = search(map, max_states);
+    map.set_facing(facing);
+    result
+}
+
+fn search(map: &mut WorldMap, max_states: usize) -> Search {
+    if map.solved() {
+        return Search {
+            outcome: Outcome::Solved(vec!()),
+            states: 1,
+        };
+    }
+    let mut seen: HashSet<State> = HashSet::new();
+    seen.insert(state(map));
+    let mut queue: VecDeque<Vec<(i32, i32)>> = VecDeque::new();
+    queue.push_back(vec!());
+    while let Some(moves) = queue.pop_front() {
+        // Replay the moves that got us here, and undo them all once we're done expanding
+        let mut frames = vec!();
+        for &dir in moves.iter() {
+            let mut frame = DeltaFrame::new();
+            map.take_turn(dir, &mut frame);
+            frames.push(frame);
+        }
+        let mut found = None;
+        for &dir in DIRECTIONS.iter() {
+            let mut frame = DeltaFrame::new();
+            let moved = map.take_turn(dir, &mut frame);
+            if moved && map.solved() && !map.caught() {
+                found = Some(dir);
+            } else if moved && !map.caught() && seen.insert(state(map)) {
+                let mut next = moves.clone();
+                next.push(dir);
+                queue.push_back(next);
+            }
+            frame.revert(map);
+            if found.is_some() {
+                break;
+            }
+        }
+        for mut frame in frames.into_iter().rev() {
+            frame.revert(map);
+        }
+        if let Some(dir) = found {
+            let mut solution = moves;
+            solution.push(dir);
+            return Search {
+                outcome: Outcome::Solved(solution),
+                states: seen.len(),
+            };
+        }
+        if seen.len() >= max_states {
+            return Search {
+                outcome: Outcome::GaveUp,
+                states: seen.len(),
+            };
+        }
+    }
+    Search {
+        outcome: Outcome::Unsolvable,
+        states: seen.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use level;
+
+    fn outcome(text: &str, max_states: usize) -> Outcome {
+        let mut map = level::parse_level(text).unwrap();
+        let before = level::write_level(&map).unwrap();
+        let facing = map.get_player_facing();
+        let search = solve(&mut map, max_states);
+        assert_eq!(level::write_level(&map).unwrap(), before, "solve() left the map changed");
+        assert_eq!(map.get_player_facing(), facing, "solve() left the player turned");
+        search.outcome
+    }
+
+    #[test]
+    fn finds_the_shortest_solution() {
+        match outcome("#######\n#@ $ .#\n#     #\n#######\n", DEFAULT_MAX_STATES) {
+            Outcome::Solved(moves) => assert_eq!(moves, vec!((1, 0), (1, 0), (1, 0))),
+            _ => panic!("expected a solution"),
+        }
+        // Getting round behind the block takes a detour
+        match outcome("######\n#   .#\n# $  #\n#@   #\n######\n", DEFAULT_MAX_STATES) {
+            Outcome::Solved(moves) => assert_eq!(moves.len(), 6),
+            _ => panic!("expected a solution"),
+        }
+    }
+
+    #[test]
+    fn already_solved_needs_no_moves() {
+        assert!(matches!(outcome("#####\n#@* #\n#####\n", DEFAULT_MAX_STATES), Outcome::Solved(ref moves) if moves.is_empty()));
+    }
+
+    #[test]
+    fn reports_unsolvable_and_giving_up() {
+        // The block is stuck in a corner
+        assert!(matches!(outcome("######\n#$  .#\n#   @#\n######\n", DEFAULT_MAX_STATES), Outcome::Unsolvable));
+        assert!(matches!(outcome("#########\n#@ $   .#\n#       #\n#########\n", 3), Outcome::GaveUp));
+    }
+
+    #[test]
+    fn big_and_small_blocks_are_told_apart() {
+        // The big block covers two cells from where it starts, the small one just its own
+        let big_first = level::parse_level("########\n#@  $  #\n########\n[big blocks]\n3 2 2 1\n").unwrap();
+        let small_first = level::parse_level("########\n#@$    #\n########\n[big blocks]\n5 2 2 1\n").unwrap();
+        assert!(state(&big_first) != state(&small_first));
+    }
+}
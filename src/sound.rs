@@ -0,0 +1,103 @@
+use DeltaFrame;
+
+use sdl2::Sdl;
+use sdl2::audio::{AudioCVT, AudioFormat, AudioQueue, AudioSpec, AudioSpecDesired, AudioSpecWAV};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Where we look for sound effects, relative to the working directory
+const SOUND_DIR: &str = "assets/sounds";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SoundEvent {
+    Step,
+    Push,
+    Blocked,
+    Undo,
+    LevelComplete,
+}
+
+impl SoundEvent {
+    const ALL: [SoundEvent; 5] = [
+        SoundEvent::Step,
+        SoundEvent::Push,
+        SoundEvent::Blocked,
+        SoundEvent::Undo,
+        SoundEvent::LevelComplete,
+    ];
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            SoundEvent::Step => "step.wav",
+            SoundEvent::Push => "push.wav",
+            SoundEvent::Blocked => "blocked.wav",
+            SoundEvent::Undo => "undo.wav",
+            SoundEvent::LevelComplete => "complete.wav",
+        }
+    }
+}
+
+/// What a move sounded like, judging by the changes it made
+pub fn move_sound(frame: &DeltaFrame, player_id: usize) -> Option<SoundEvent> {
+    frame.player_motion(player_id).map(|(_, pushed)| if pushed { SoundEvent::Push } else { SoundEvent::Step })
+}
+
+/// Sound effects, converted up front to whatever the audio device wants
+/// Without an audio device (or without the sound files) this quietly does nothing.
+pub struct Sounds {
+    queue: Option<AudioQueue<i16>>,
+    clips: HashMap<SoundEvent, Vec<i16>>,
+}
+
+impl Sounds {
+    pub fn new(sdl: &Sdl) -> Sounds {
+        let queue = match open_queue(sdl) {
+            Ok(queue) => Some(queue),
+            Err(e) => {
+                eprintln!("No audio device ({}), playing silently", e);
+                None
+            },
+        };
+        let mut clips = HashMap::new();
+        if let Some(ref queue) = queue {
+            for event in SoundEvent::ALL.iter() {
+                let path = Path::new(SOUND_DIR).join(event.file_name());
+                if let Ok(clip) = load_clip(&path, queue.spec()) {
+                    clips.insert(*event, clip);
+                }
+            }
+        }
+        Sounds {
+            queue,
+            clips,
+        }
+    }
+
+    pub fn play(&self, event: SoundEvent) {
+        if let (Some(queue), Some(clip)) = (self.queue.as_ref(), self.clips.get(&event)) {
+            // Effects are short and the newest one matters most, so cut off whatever was playing
+            queue.clear();
+            queue.queue(clip);
+        }
+    }
+}
+
+fn open_queue(sdl: &Sdl) -> Result<AudioQueue<i16>, String> {
+    let audio_subsystem = sdl.audio()?;
+    let desired = AudioSpecDesired {
+        freq: Some(44_100),
+        channels: Some(2),
+        samples: None,
+    };
+    let queue = audio_subsystem.open_queue::<i16, _>(None, &desired)?;
+    queue.resume();
+    Ok(queue)
+}
+
+fn load_clip(path: &Path, spec: &AudioSpec) -> Result<Vec<i16>, String> {
+    let wav = AudioSpecWAV::load_wav(path)?;
+    let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, AudioFormat::s16_sys(), spec.channels, spec.freq)?;
+    let bytes = cvt.convert(wav.buffer().to_vec());
+    Ok(bytes.chunks(2).map(|pair| i16::from_ne_bytes([pair[0], pair[1]])).collect())
+}
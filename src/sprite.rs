@@ -0,0 +1,166 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, RenderTarget, Texture, TextureCreator};
+use sdl2::surface::Surface;
+
+use Tint;
+
+use std::cell::RefCell;
+use std::path::Path;
+
+/// Where we look for the tile atlas, relative to the working directory
+pub const ATLAS_PATH: &str = "assets/tiles.bmp";
+
+/// Size in pixels of one tile in the atlas image (tiles are scaled to fit the map)
+const ATLAS_TILE_SIZE: u32 = 32;
+
+/// Which picture in the atlas an object wants to be drawn with
+/// Each kind of sprite gets one row of the atlas; extra columns are animation/facing frames
+#[derive(Clone, Copy)]
+pub enum Sprite {
+    Player(u32),
+    Block(Tint),
+    Wall,
+    Goal(Tint),
+    Key(Tint),
+    Door(Tint),
+    // Which way the belt runs
+    Conveyor((i32, i32)),
+    BreakableWall,
+    Bomb,
+    Switch,
+    // Frame 0 for chasers, 1 + direction for patrollers
+    Enemy(u32),
+}
+
+impl Sprite {
+    fn row(&self) -> u32 {
+        match self {
+            Sprite::Player(_) => 0,
+            Sprite::Block(_) => 1,
+            Sprite::Wall => 2,
+            Sprite::Goal(_) => 3,
+            Sprite::Key(_) => 4,
+            Sprite::Door(_) => 5,
+            Sprite::Conveyor(_) => 6,
+            Sprite::BreakableWall => 7,
+            Sprite::Bomb => 8,
+            Sprite::Switch => 9,
+            Sprite::Enemy(_) => 10,
+        }
+    }
+
+    // Colored objects share a sprite, which gets tinted when drawn
+    fn tint(&self) -> Tint {
+        match self {
+            Sprite::Block(tint) | Sprite::Goal(tint) | Sprite::Key(tint) | Sprite::Door(tint) => *tint,
+            _ => Tint::Plain,
+        }
+    }
+
+    fn frame(&self) -> u32 {
+        match self {
+            Sprite::Player(frame) | Sprite::Enemy(frame) => *frame,
+            Sprite::Conveyor(dir) => direction_frame(*dir),
+            _ => 0,
+        }
+    }
+}
+
+/// Frame for a sprite that faces one of the four directions: up, right, down, then left
+pub fn direction_frame(dir: (i32, i32)) -> u32 {
+    match dir {
+        (0, -1) => 0,
+        (1, 0) => 1,
+        (0, 1) => 2,
+        _ => 3,
+    }
+}
+
+/// A small square just inside the edge of `rect` on the `dir` side, for sprite-less facing
+pub fn facing_mark(rect: Rect, (dx, dy): (i32, i32)) -> Rect {
+    let size = (rect.width() / 4).max(1);
+    let (cx, cy) = (rect.center().x(), rect.center().y());
+    let reach = (rect.width() / 2 - size) as i32;
+    Rect::from_center((cx + dx * reach, cy + dy * reach), size, size)
+}
+
+/// Anything objects can be drawn onto: the game window, or an offscreen surface for render
+/// Objects are drawn through trait objects, so this can't just be a generic Canvas.
+pub trait Paint {
+    fn set_draw_color(&mut self, color: Color);
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), String>;
+    fn copy(&mut self, texture: &Texture, src: Rect, dest: Rect) -> Result<(), String>;
+}
+
+impl<T: RenderTarget> Paint for Canvas<T> {
+    fn set_draw_color(&mut self, color: Color) {
+        Canvas::set_draw_color(self, color);
+    }
+
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), String> {
+        Canvas::fill_rect(self, rect)
+    }
+
+    fn copy(&mut self, texture: &Texture, src: Rect, dest: Rect) -> Result<(), String> {
+        Canvas::copy(self, texture, src, dest)
+    }
+}
+
+/// A grid of square tiles loaded from a BMP file
+/// Pure magenta (255, 0, 255) is treated as transparent
+pub struct TileAtlas<'a> {
+    // Tinting changes the texture's color mod, which needs mutable access
+    texture: RefCell<Texture<'a>>,
+    columns: u32,
+    rows: u32,
+}
+
+impl<'a> TileAtlas<'a> {
+    pub fn load<T, P: AsRef<Path>>(texture_creator: &'a TextureCreator<T>, path: P) -> Result<TileAtlas<'a>, String> {
+        let mut surface = Surface::load_bmp(path)?;
+        surface.set_color_key(true, Color::RGB(255, 0, 255))?;
+        let texture = texture_creator.create_texture_from_surface(&surface).map_err(|e| e.to_string())?;
+        let query = texture.query();
+        if query.width < ATLAS_TILE_SIZE || query.height < ATLAS_TILE_SIZE {
+            return Err(format!("Tile atlas must be at least {0}x{0} pixels", ATLAS_TILE_SIZE));
+        }
+        Ok(TileAtlas {
+            texture: RefCell::new(texture),
+            columns: query.width / ATLAS_TILE_SIZE,
+            rows: query.height / ATLAS_TILE_SIZE,
+        })
+    }
+
+    // Returns false if the atlas doesn't have a tile for this sprite
+    // A missing frame falls back to the first frame of the row
+    fn draw(&self, canvas: &mut dyn Paint, sprite: Sprite, dest: Rect) -> bool {
+        let row = sprite.row();
+        if row >= self.rows {
+            return false;
+        }
+        let column = if sprite.frame() < self.columns { sprite.frame() } else { 0 };
+        let src = Rect::new(
+            (column * ATLAS_TILE_SIZE) as i32,
+            (row * ATLAS_TILE_SIZE) as i32,
+            ATLAS_TILE_SIZE,
+            ATLAS_TILE_SIZE,
+        );
+        let mut texture = self.texture.borrow_mut();
+        let m = sprite.tint().modulation();
+        texture.set_color_mod(m.r, m.g, m.b);
+        canvas.copy(&texture, src, dest).expect("Failed to draw sprite");
+        true
+    }
+}
+
+/// Draw the sprite from the atlas if we have one, and a plain colored rect otherwise
+pub fn draw_tile(canvas: &mut dyn Paint, atlas: Option<&TileAtlas>, sprite: Sprite, color: Color, dest: Rect) {
+    if let Some(atlas) = atlas {
+        if atlas.draw(canvas, sprite, dest) {
+            return;
+        }
+    }
+    canvas.set_draw_color(color);
+    canvas.fill_rect(dest).expect("Failed to draw tile rect");
+}
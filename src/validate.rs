@@ -0,0 +1,212 @@
+//! Sanity checks for a level, catching mistakes that would make it unplayable or unsolvable
+//! before anyone tries to play it.
+
+use {Layer, ObjectKind, Tint, WorldMap, DIRECTIONS, NUMBER_OF_LAYERS};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Everything wrong with the level, in a form fit to show to a level designer
+/// An empty list means the level passed every check.
+pub fn validate(map: &WorldMap) -> Vec<String> {
+    let mut problems = vec!();
+    check_cells(map, &mut problems);
+    let one_player = check_players(map, &mut problems);
+    check_counts(map, &mut problems);
+    // Without exactly one player, there's nowhere to walk from
+    if one_player {
+        check_reachable_goals(map, &mut problems);
+    }
+    check_dead_blocks(map, &mut problems);
+    problems
+}
+
+// Every object in the map, with the position of the cell that holds it
+fn objects(map: &WorldMap) -> Vec<((i32, i32), ObjectKind)> {
+    let mut objects = vec!();
+    for x in 0..map.width {
+        for y in 0..map.height {
+            for index in 0..NUMBER_OF_LAYERS {
+                for object in map.map[x as usize][y as usize].layers[index].iter() {
+                    objects.push(((x, y), object.kind()));
+                }
+            }
+        }
+    }
+    objects
+}
+
+// Stacked objects, and objects whose idea of where they are doesn't match where they're kept
+fn check_cells(map: &WorldMap, problems: &mut Vec<String>) {
+    for x in 0..map.width {
+        for y in 0..map.height {
+            for index in 0..NUMBER_OF_LAYERS {
+                let objects = &map.map[x as usize][y as usize].layers[index];
+                if objects.len() > 1 {
+                    let kinds: Vec<ObjectKind> = objects.iter().map(|object| object.kind()).collect();
+                    problems.push(format!("{:?} are stacked in one layer at {:?}", kinds, (x, y)));
+                }
+                for object in objects.iter() {
+                    if object.get_pos() != (x, y) {
+                        problems.push(format!("{:?} is kept at {:?} but thinks it's at {:?}", object.kind(), (x, y), object.get_pos()));
+                    }
+                    if object.cells().iter().any(|&(cx, cy)| map.invalid(cx, cy)) {
+                        problems.push(format!("{:?} at {:?} sticks out of the level", object.kind(), (x, y)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn check_players(map: &WorldMap, problems: &mut Vec<String>) -> bool {
+    let players: Vec<(i32, i32)> = objects(map).into_iter()
+        .filter(|&(_, kind)| kind == ObjectKind::Player)
+        .map(|(pos, _)| pos)
+        .collect();
+    match players.len() {
+        0 => problems.push("There's no player".to_string()),
+        1 => {},
+        _ => problems.push(format!("There are {} players, at {:?}", players.len(), players)),
+    }
+    players.len() == 1
+}
+
+#[derive(Default)]
+struct Count {
+    blocks: usize,
+    // How many goals the blocks could cover at once; a big block can cover one per cell
+    cells: usize,
+    goals: usize,
+}
+
+// Each color's blocks have to be able to cover all of its goals
+// With only plain blocks that means exactly as many blocks as goals, but
+// a big block can sit partly off the goals, so it's allowed to cover more.
+fn check_counts(map: &WorldMap, problems: &mut Vec<String>) {
+    let mut counts: HashMap<Tint, Count> = HashMap::new();
+    for x in 0..map.width {
+        for y in 0..map.height {
+            let cell = &map.map[x as usize][y as usize];
+            for object in cell.layers.iter().flat_map(|objects| objects.iter()) {
+                match object.kind() {
+                    ObjectKind::Block(tint) => {
+                        let (width, height) = object.size();
+                        let count = counts.entry(tint).or_default();
+                        count.blocks += 1;
+                        count.cells += (width * height) as usize;
+                    },
+                    ObjectKind::Goal(tint) => counts.entry(tint).or_default().goals += 1,
+                    _ => {},
+                }
+            }
+        }
+    }
+    let mut counts: Vec<(Tint, Count)> = counts.into_iter().collect();
+    counts.sort_by_key(|&(tint, _)| tint as usize);
+    for (tint, count) in counts {
+        if count.cells < count.goals {
+            problems.push(format!("{} {:?} goals but the {:?} blocks can only cover {}", count.goals, tint, tint, count.cells));
+        } else if count.cells == count.blocks && count.blocks != count.goals {
+            problems.push(format!("{} {:?} blocks but {} {:?} goals", count.blocks, tint, count.goals, tint));
+        }
+    }
+    if !objects(map).iter().any(|&(_, kind)| matches!(kind, ObjectKind::Goal(_))) {
+        problems.push("There are no goals, so the level can never be solved".to_string());
+    }
+}
+
+// Walls are the only things that never go away
+fn is_wall(map: &WorldMap, (x, y): (i32, i32)) -> bool {
+    map.invalid(x, y) || map.peek(x, y, &Layer::Solid).is_some_and(|object| object.kind() == ObjectKind::Wall)
+}
+
+// The player has to be able to get next to a goal to push a block onto it
+fn check_reachable_goals(map: &WorldMap, problems: &mut Vec<String>) {
+    let start = map.get_player_pos();
+    let mut reached: HashSet<(i32, i32)> = HashSet::new();
+    reached.insert(start);
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some((x, y)) = queue.pop_front() {
+        for &(dx, dy) in DIRECTIONS.iter() {
+            let next = (x + dx, y + dy);
+            if !is_wall(map, next) && reached.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    for (pos, kind) in objects(map) {
+        if let ObjectKind::Goal(_) = kind {
+            if !reached.contains(&pos) {
+                problems.push(format!("The goal at {:?} can't be reached", pos));
+            }
+        }
+    }
+}
+
+// A block on a dead square can never be pushed onto a goal of its color
+// Live squares are found by pulling blocks backwards away from each goal.
+fn check_dead_blocks(map: &WorldMap, problems: &mut Vec<String>) {
+    let mut live: HashMap<Tint, HashSet<(i32, i32)>> = HashMap::new();
+    for (goal, kind) in objects(map) {
+        let tint = match kind {
+            ObjectKind::Goal(tint) => tint,
+            _ => continue,
+        };
+        let squares = live.entry(tint).or_default();
+        if !squares.insert(goal) {
+            continue;
+        }
+        let mut queue = VecDeque::new();
+        queue.push_back(goal);
+        while let Some((x, y)) = queue.pop_front() {
+            for &(dx, dy) in DIRECTIONS.iter() {
+                // The block came from (x+dx, y+dy), pushed by a player standing one further out
+                let from = (x + dx, y + dy);
+                let stand = (x + 2 * dx, y + 2 * dy);
+                if !is_wall(map, from) && !is_wall(map, stand) && squares.insert(from) {
+                    queue.push_back(from);
+                }
+            }
+        }
+    }
+    for x in 0..map.width {
+        for y in 0..map.height {
+            if let Some(object) = map.map[x as usize][y as usize].peek(&Layer::Solid) {
+                if let ObjectKind::Block(tint) = object.kind() {
+                    // Dead squares only make sense for blocks that fit in one cell
+                    if object.size() == (1, 1) && !live.get(&tint).is_some_and(|squares| squares.contains(&(x, y))) {
+                        problems.push(format!("The {:?} block at {:?} starts on a dead square", tint, (x, y)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use level;
+
+    #[test]
+    fn clean_level_has_no_problems() {
+        let map = level::parse_level("#######\n#@ $ .#\n#     #\n#######\n").unwrap();
+        assert!(validate(&map).is_empty());
+    }
+
+    #[test]
+    fn big_block_covers_several_goals() {
+        let text = "#######\n#@    #\n#  .. #\n#  .. #\n#     #\n#######\n[big blocks]\n2 3 2 2\n";
+        let map = level::parse_level(text).unwrap();
+        assert!(!validate(&map).iter().any(|problem| problem.contains("goals")));
+    }
+
+    #[test]
+    fn counts_and_dead_blocks_are_reported() {
+        let map = level::parse_level("#######\n#@   .#\n#$   .#\n#######\n").unwrap();
+        let problems = validate(&map);
+        assert!(problems.iter().any(|problem| problem.contains("can only cover 1")));
+        assert!(problems.iter().any(|problem| problem.contains("dead square")));
+    }
+}